@@ -0,0 +1,206 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::{
+    gallery::{self, GalleryImage},
+    galleryexif::GalleryFilter,
+    project::{Project, ProjectMetadata, ProjectTag, TagFilter},
+    search::search_projects,
+    AppState, GALLERY_PAGE_SIZE,
+};
+
+/// JSON counterparts of the HTML pages, with the same query parameters
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/projects", get(projects))
+        .route("/projects/:slug", get(project))
+        .route("/search", get(search))
+        .route("/tags", get(tags))
+        .route("/gallery", get(gallery_page))
+        .route("/gallery/:slug", get(gallery_image))
+}
+
+#[derive(Serialize)]
+struct ApiProject<'a> {
+    #[serde(flatten)]
+    metadata: &'a ProjectMetadata,
+    body_html: &'a str,
+}
+
+impl<'a> From<&'a Project> for ApiProject<'a> {
+    fn from(project: &'a Project) -> Self {
+        ApiProject {
+            metadata: &project.metadata,
+            body_html: &project.body_html,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TagCount<'a> {
+    #[serde(flatten)]
+    tag: &'a ProjectTag,
+    count: usize,
+}
+
+#[derive(Serialize)]
+struct TagCountGroup<'a> {
+    category: &'a str,
+    tags: Vec<TagCount<'a>>,
+}
+
+#[derive(Serialize)]
+struct ProjectList<'a> {
+    filter: TagFilter,
+    /// tags of the filtered projects with the number of projects having them
+    tag_counts: Vec<TagCountGroup<'a>>,
+    projects: Vec<ApiProject<'a>>,
+}
+
+async fn projects(
+    State(state): State<AppState>,
+    Query(query): Query<Vec<(String, String)>>,
+) -> Result<Response, StatusCode> {
+    let filter = TagFilter::from_query(&query).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let catalog = state.project_catalog.load();
+    let tag_counts = catalog.tag_counts(&filter);
+    let list = ProjectList {
+        tag_counts: tag_counts
+            .iter()
+            .map(|(category, tags)| TagCountGroup {
+                category,
+                tags: tags
+                    .iter()
+                    .map(|(tag, count)| TagCount { tag, count: *count })
+                    .collect(),
+            })
+            .collect(),
+        projects: catalog
+            .published()
+            .filter(|p| filter.matches(p))
+            .map(ApiProject::from)
+            .collect(),
+        filter,
+    };
+    Ok(Json(&list).into_response())
+}
+
+async fn project(
+    State(state): State<AppState>,
+    Path(slug): Path<String>,
+) -> Result<Response, StatusCode> {
+    let catalog = state.project_catalog.load();
+    let project = catalog.find_published(&slug).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(&ApiProject::from(project)).into_response())
+}
+
+#[derive(Serialize)]
+struct SearchResult<'a> {
+    project: ApiProject<'a>,
+    title_html: &'a str,
+    snippet_html: &'a str,
+}
+
+#[derive(Serialize)]
+struct SearchResults<'a> {
+    query: &'a str,
+    filter: &'a TagFilter,
+    results: Vec<SearchResult<'a>>,
+}
+
+async fn search(
+    State(state): State<AppState>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Response, StatusCode> {
+    let query = params
+        .iter()
+        .find(|(key, _)| key == "q")
+        .map(|(_, q)| q.trim())
+        .unwrap_or_default();
+    let filter = TagFilter::from_query(&params).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let catalog = state.project_catalog.load();
+    let results = search_projects(&catalog, query, &filter);
+    Ok(Json(&SearchResults {
+        query,
+        filter: &filter,
+        results: results
+            .iter()
+            .map(|r| SearchResult {
+                project: ApiProject::from(r.project),
+                title_html: &r.title_html,
+                snippet_html: &r.snippet_html,
+            })
+            .collect(),
+    })
+    .into_response())
+}
+
+#[derive(Serialize)]
+struct TagGroup<'a> {
+    category: &'a str,
+    tags: &'a [ProjectTag],
+}
+
+async fn tags(State(state): State<AppState>) -> Result<Response, StatusCode> {
+    let catalog = state.project_catalog.load();
+    let tag_groups = catalog.tag_groups();
+    let groups: Vec<TagGroup> = tag_groups
+        .iter()
+        .map(|(category, tags)| TagGroup { category, tags })
+        .collect();
+    Ok(Json(&groups).into_response())
+}
+
+#[derive(Serialize)]
+struct GalleryPage<'a> {
+    page: usize,
+    total_pages: usize,
+    images: &'a [&'a GalleryImage],
+}
+
+async fn gallery_page(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, StatusCode> {
+    let page: usize = params
+        .get("p")
+        .map_or(1, |page_str| page_str.parse().unwrap_or(1));
+    let filter = GalleryFilter::from_query(&params);
+    let gallery = state.gallery.load();
+    let images = gallery.filtered(&filter);
+    Ok(Json(&GalleryPage {
+        page,
+        total_pages: gallery::total_pages(images.len(), GALLERY_PAGE_SIZE),
+        images: gallery::page(&images, page, GALLERY_PAGE_SIZE),
+    })
+    .into_response())
+}
+
+#[derive(Serialize)]
+struct FoundGalleryImage<'a> {
+    image: &'a GalleryImage,
+    /// filenames of the neighbouring images, newer and older respectively
+    prev: Option<&'a str>,
+    next: Option<&'a str>,
+}
+
+async fn gallery_image(
+    State(state): State<AppState>,
+    Path(slug): Path<String>,
+) -> Result<Response, StatusCode> {
+    let gallery = state.gallery.load();
+    let found = gallery.find(&slug).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(&FoundGalleryImage {
+        image: found.image,
+        prev: found.prev.map(|img| img.filename.as_str()),
+        next: found.next.map(|img| img.filename.as_str()),
+    })
+    .into_response())
+}
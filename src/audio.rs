@@ -0,0 +1,540 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey},
+    probe::Hint,
+};
+
+use crate::health::LoadFailure;
+use crate::projectmedia::is_fresh;
+
+/// formats symphonia is built to decode
+const AUDIO_EXTENSIONS: [&str; 7] = ["mp3", "flac", "ogg", "oga", "wav", "m4a", "aac"];
+
+/// number of bars in the waveform scrubber
+const WAVEFORM_BARS: usize = 160;
+/// frames per peak measured while decoding, before they are merged into the bars
+const PEAK_WINDOW: usize = 1024;
+/// `/music/tracks/media` serves covers and waveforms, a track page there would be shadowed
+const RESERVED_SLUG: &str = "media";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AudioError {
+    #[error("Failed to access {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("Audio filename {path:?} contains non-unicode characters")]
+    NonUnicodeFilename { path: PathBuf },
+    #[error("Failed to decode audio file {path:?}: {source}")]
+    Decode {
+        path: PathBuf,
+        source: SymphoniaError,
+    },
+    #[error("Audio file {path:?} has no decodable tracks")]
+    NoTrack { path: PathBuf },
+    #[error("Audio files {path:?} and {other:?} are both served as {slug:?}")]
+    DuplicateSlug {
+        path: PathBuf,
+        other: PathBuf,
+        slug: String,
+    },
+    #[error("Audio file {path:?} can't be served as {slug:?}, the URL is taken by track media")]
+    ReservedSlug { path: PathBuf, slug: String },
+    #[error("Audio file {path:?} has no letters or digits in its name to make the track URL from")]
+    EmptySlug { path: PathBuf },
+    #[error("Failed to write track info {path:?}: {source}")]
+    Serialize {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+impl AudioError {
+    pub fn io(path: &Path) -> impl FnOnce(io::Error) -> AudioError + '_ {
+        move |source| AudioError::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    fn decode(path: &Path) -> impl FnOnce(SymphoniaError) -> AudioError + '_ {
+        move |source| AudioError::Decode {
+            path: path.to_owned(),
+            source,
+        }
+    }
+}
+
+/// MIME type of an audio file by its extension
+pub fn mime_type(file: &str) -> &'static str {
+    let extension = Path::new(file)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "mp3" => "audio/mpeg",
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "m4a" | "aac" => "audio/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+/// a single audio file from the audio dir, with the info read from its tags and contents; the
+/// info is cached as `<slug>.json` in the audio media dir, next to the extracted cover art
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AudioTrack {
+    /// path inside the audio dir
+    pub file: String,
+    pub slug: String,
+    /// title tag, falls back to the filename
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    /// in seconds
+    pub duration: f64,
+    /// filename in the audio media dir
+    pub cover: Option<String>,
+    /// loudest sample in each of the evenly spaced slices of the track, scaled so that the
+    /// loudest one is 255
+    pub peaks: Vec<u8>,
+}
+
+/// a single bar of the waveform in the 0-100 coordinate space, vertically centered
+pub struct WaveformBar {
+    pub x: f32,
+    pub y: f32,
+    pub height: f32,
+}
+
+impl AudioTrack {
+    pub fn mime_type(&self) -> &'static str {
+        mime_type(&self.file)
+    }
+
+    /// e.g. "3:07"
+    pub fn duration_display(&self) -> String {
+        let seconds = self.duration.round() as u64;
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+
+    pub fn waveform_width(&self) -> usize {
+        self.peaks.len()
+    }
+
+    pub fn waveform(&self) -> Vec<WaveformBar> {
+        self.peaks
+            .iter()
+            .enumerate()
+            .map(|(i, &peak)| {
+                // silence is still shown as a thin line
+                let height = (peak as f32 / 255.0 * 100.0).max(1.0);
+                WaveformBar {
+                    x: i as f32,
+                    y: (100.0 - height) / 2.0,
+                    height,
+                }
+            })
+            .collect()
+    }
+
+    /// reads the tags and decodes the whole file to measure the duration and the peaks,
+    /// extracting the embedded cover art into `media_dir`
+    fn analyze(
+        path: &Path,
+        file: String,
+        slug: String,
+        media_dir: &Path,
+    ) -> Result<AudioTrack, AudioError> {
+        let source = File::open(path).map_err(AudioError::io(path))?;
+        let stream = MediaSourceStream::new(Box::new(source), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension() {
+            hint.with_extension(&extension.to_string_lossy());
+        }
+        let mut probed = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .map_err(AudioError::decode(path))?;
+
+        // tags may come both from the container (e.g. ID3 before the MP3 stream) and the
+        // format itself (e.g. Vorbis comments)
+        let mut revisions: Vec<MetadataRevision> = Vec::new();
+        if let Some(revision) = probed
+            .metadata
+            .get()
+            .and_then(|mut m| m.skip_to_latest().cloned())
+        {
+            revisions.push(revision);
+        }
+        let mut format = probed.format;
+        if let Some(revision) = format.metadata().skip_to_latest() {
+            revisions.push(revision.clone());
+        }
+        let tag = |key: StandardTagKey| {
+            revisions
+                .iter()
+                .flat_map(|r| r.tags())
+                .find(|t| t.std_key == Some(key))
+                // RIFF INFO values keep their padding
+                .map(|t| {
+                    t.value
+                        .to_string()
+                        .trim_matches(|c: char| c.is_whitespace() || c == '\0')
+                        .to_owned()
+                })
+                .filter(|v| !v.is_empty())
+        };
+        let title = tag(StandardTagKey::TrackTitle).unwrap_or_else(|| {
+            Path::new(&file)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or(file.clone())
+        });
+        let artist = tag(StandardTagKey::Artist).or_else(|| tag(StandardTagKey::AlbumArtist));
+        let album = tag(StandardTagKey::Album);
+        // e.g. "3/12"
+        let track_number = tag(StandardTagKey::TrackNumber)
+            .and_then(|n| n.split('/').next().and_then(|n| n.trim().parse().ok()));
+
+        let visuals: Vec<_> = revisions.iter().flat_map(|r| r.visuals()).collect();
+        let cover_art = visuals
+            .iter()
+            .find(|v| v.usage == Some(StandardVisualKey::FrontCover))
+            .or(visuals.first());
+        let mut cover = None;
+        if let Some(visual) = cover_art {
+            let extension = match visual.media_type.as_str() {
+                "image/jpeg" | "image/jpg" => Some("jpg"),
+                "image/png" => Some("png"),
+                "image/webp" => Some("webp"),
+                "image/gif" => Some("gif"),
+                _ => None,
+            };
+            if let Some(extension) = extension {
+                let filename = format!("{}.cover.{}", slug, extension);
+                let cover_path = media_dir.join(&filename);
+                fs::write(&cover_path, &visual.data).map_err(AudioError::io(&cover_path))?;
+                cover = Some(filename);
+            }
+        }
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| AudioError::NoTrack {
+                path: path.to_owned(),
+            })?;
+        let track_id = track.id;
+        let mut sample_rate = track.codec_params.sample_rate;
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(AudioError::decode(path))?;
+
+        let mut window_peaks: Vec<f32> = Vec::new();
+        let mut window_peak: f32 = 0.0;
+        let mut window_frames: usize = 0;
+        let mut total_frames: u64 = 0;
+        let mut samples: Option<SampleBuffer<f32>> = None;
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    break
+                }
+                Err(SymphoniaError::ResetRequired) => break,
+                Err(e) => return Err(AudioError::decode(path)(e)),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
+            let decoded = match decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // a corrupted packet or two is not worth failing the whole track
+                Err(SymphoniaError::DecodeError(e)) => {
+                    tracing::debug!("Skipping malformed packet in {:?}: {}", path, e);
+                    continue;
+                }
+                Err(e) => return Err(AudioError::decode(path)(e)),
+            };
+            let spec = *decoded.spec();
+            sample_rate = sample_rate.or(Some(spec.rate));
+            let channels = spec.channels.count().max(1);
+            let buffer = match &mut samples {
+                Some(buffer) if buffer.capacity() >= decoded.capacity() * channels => buffer,
+                _ => samples.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+            };
+            buffer.copy_interleaved_ref(decoded);
+            for frame in buffer.samples().chunks(channels) {
+                let peak = frame.iter().fold(0.0f32, |acc, s| acc.max(s.abs()));
+                window_peak = window_peak.max(peak);
+                window_frames += 1;
+                if window_frames == PEAK_WINDOW {
+                    window_peaks.push(window_peak);
+                    window_peak = 0.0;
+                    window_frames = 0;
+                }
+            }
+            total_frames += (buffer.samples().len() / channels) as u64;
+        }
+        if window_frames > 0 {
+            window_peaks.push(window_peak);
+        }
+
+        let duration = match sample_rate {
+            Some(rate) if rate > 0 => total_frames as f64 / rate as f64,
+            _ => 0.0,
+        };
+        Ok(AudioTrack {
+            file,
+            slug,
+            title,
+            artist,
+            album,
+            track_number,
+            duration,
+            cover,
+            peaks: waveform_peaks(&window_peaks),
+        })
+    }
+}
+
+/// merges per-window peaks into the waveform bars, normalized to the loudest one
+fn waveform_peaks(window_peaks: &[f32]) -> Vec<u8> {
+    if window_peaks.is_empty() {
+        return Vec::new();
+    }
+    let bars: Vec<f32> = (0..WAVEFORM_BARS)
+        .map(|i| {
+            let start = i * window_peaks.len() / WAVEFORM_BARS;
+            let end = ((i + 1) * window_peaks.len() / WAVEFORM_BARS).max(start + 1);
+            window_peaks[start.min(window_peaks.len() - 1)..end.min(window_peaks.len())]
+                .iter()
+                .fold(0.0f32, |acc, &p| acc.max(p))
+        })
+        .collect();
+    let loudest = bars.iter().fold(0.0f32, |acc, &p| acc.max(p));
+    bars.iter()
+        .map(|&p| {
+            if loudest > 0.0 {
+                (p / loudest * 255.0).round() as u8
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+/// audio files in the dir and its subdirs, as paths relative to it
+/// audio files in the audio dir and its subdirs, relative to it, sorted
+pub fn list(audio_dir: &Path) -> Result<Vec<PathBuf>, AudioError> {
+    let mut paths = Vec::new();
+    sources(audio_dir, Path::new(""), &mut paths)?;
+    paths.sort();
+    Ok(paths)
+}
+
+/// track page URL segment, made of the file's `relative` path; `path` is for the errors
+pub fn track_slug(path: &Path, relative: &Path) -> Result<String, AudioError> {
+    let slug = slugify::slugify(
+        &relative.with_extension("").to_string_lossy(),
+        "",
+        "-",
+        None,
+    );
+    if slug.is_empty() {
+        return Err(AudioError::EmptySlug {
+            path: path.to_owned(),
+        });
+    }
+    if slug == RESERVED_SLUG {
+        return Err(AudioError::ReservedSlug {
+            path: path.to_owned(),
+            slug,
+        });
+    }
+    Ok(slug)
+}
+
+fn sources(audio_dir: &Path, subdir: &Path, found: &mut Vec<PathBuf>) -> Result<(), AudioError> {
+    let dir = audio_dir.join(subdir);
+    for entry in dir.read_dir().map_err(AudioError::io(&dir))?.flatten() {
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            sources(audio_dir, &subdir.join(&name), found)?;
+        } else if path.extension().is_some_and(|ext| {
+            AUDIO_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+        }) {
+            found.push(subdir.join(&name));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AudioCatalog {
+    /// ordered by album and track number, then by path, with tracks outside albums last
+    pub tracks: Vec<AudioTrack>,
+    /// files that failed to load
+    pub failed: Vec<LoadFailure>,
+    /// the catalog is loaded in the background, the site starts with an empty one
+    pub loading: bool,
+}
+
+impl std::fmt::Display for AudioCatalog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "AudioCatalog {{ {} tracks, {} failed }}",
+            self.tracks.len(),
+            self.failed.len()
+        )
+    }
+}
+
+impl AudioCatalog {
+    /// placeholder served until the actual catalog is loaded
+    pub fn loading() -> AudioCatalog {
+        AudioCatalog {
+            loading: true,
+            ..Default::default()
+        }
+    }
+
+    /// missing audio dir is not an error, the site just has no tracks; info on the tracks
+    /// unchanged since the previous load is read from `media_dir`, the rest of it is removed
+    pub fn load(audio_dir: &Path, media_dir: &Path) -> Result<AudioCatalog, AudioError> {
+        if !audio_dir.is_dir() {
+            tracing::warn!(
+                "Audio dir {:?} does not exist, no tracks to show",
+                audio_dir
+            );
+            return Ok(AudioCatalog::default());
+        }
+        tracing::info!("Loading audio catalog from {:?}", audio_dir);
+        let paths = list(audio_dir)?;
+
+        let mut catalog = AudioCatalog::default();
+        let mut slugs: HashMap<String, PathBuf> = HashMap::new();
+        let mut expected: HashSet<String> = HashSet::new();
+        for relative in paths {
+            let path = audio_dir.join(&relative);
+            match AudioCatalog::load_track(&path, &relative, media_dir, &mut slugs) {
+                Ok(track) => {
+                    expected.insert(format!("{}.json", track.slug));
+                    expected.extend(track.cover.clone());
+                    catalog.tracks.push(track);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to load audio track from {:?}: {}", path, e);
+                    catalog.failed.push(LoadFailure {
+                        path,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        catalog.tracks.sort_by_key(|t| {
+            (
+                t.album.is_none(),
+                t.album.clone(),
+                t.track_number,
+                t.file.clone(),
+            )
+        });
+
+        for entry in media_dir
+            .read_dir()
+            .map_err(AudioError::io(media_dir))?
+            .flatten()
+        {
+            if !expected.contains(entry.file_name().to_string_lossy().as_ref()) {
+                tracing::debug!("Removing stale audio media file {:?}", entry.path());
+                let path = entry.path();
+                fs::remove_file(&path).map_err(AudioError::io(&path))?;
+            }
+        }
+        Ok(catalog)
+    }
+
+    fn load_track(
+        path: &Path,
+        relative: &Path,
+        media_dir: &Path,
+        slugs: &mut HashMap<String, PathBuf>,
+    ) -> Result<AudioTrack, AudioError> {
+        let file = relative
+            .to_str()
+            .ok_or_else(|| AudioError::NonUnicodeFilename {
+                path: path.to_owned(),
+            })?
+            .to_owned();
+        let slug = track_slug(path, relative)?;
+        if let Some(other) = slugs.insert(slug.clone(), path.to_owned()) {
+            return Err(AudioError::DuplicateSlug {
+                path: path.to_owned(),
+                other,
+                slug,
+            });
+        }
+
+        let info_path = media_dir.join(format!("{}.json", slug));
+        if is_fresh(&info_path, path) {
+            let cached = fs::read(&info_path)
+                .ok()
+                .and_then(|info| serde_json::from_slice::<AudioTrack>(&info).ok())
+                .filter(|track| track.file == file);
+            if let Some(track) = cached {
+                return Ok(track);
+            }
+        }
+        tracing::debug!("Analyzing audio file {:?}", path);
+        let track = AudioTrack::analyze(path, file, slug, media_dir)?;
+        let info = serde_json::to_vec(&track).map_err(|source| AudioError::Serialize {
+            path: info_path.clone(),
+            source,
+        })?;
+        fs::write(&info_path, info).map_err(AudioError::io(&info_path))?;
+        Ok(track)
+    }
+
+    pub fn find(&self, slug: &str) -> Option<&AudioTrack> {
+        self.tracks.iter().find(|t| t.slug == slug)
+    }
+
+    pub fn find_by_file(&self, file: &str) -> Option<&AudioTrack> {
+        self.tracks.iter().find(|t| t.file == file)
+    }
+
+    /// tracks before and after the given one, in the catalog order
+    pub fn neighbours(&self, slug: &str) -> (Option<&AudioTrack>, Option<&AudioTrack>) {
+        let Some(idx) = self.tracks.iter().position(|t| t.slug == slug) else {
+            return (None, None);
+        };
+        (
+            idx.checked_sub(1).and_then(|i| self.tracks.get(i)),
+            self.tracks.get(idx + 1),
+        )
+    }
+}
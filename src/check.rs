@@ -0,0 +1,447 @@
+use comrak::{nodes::NodeValue, Arena};
+use regex::Regex;
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::audio;
+use crate::date::Date;
+use crate::gallery::GalleryError;
+use crate::galleryalbums::{self, ALBUMS_FILENAME};
+use crate::music::{self, MusicEntry};
+use crate::project::{self, ProjectMetadata, ProjectTag};
+use crate::{gallery, projectmedia, ContentDirs};
+
+/// something wrong with the content, pointing to the file and, when possible, the line
+struct Problem {
+    file: PathBuf,
+    line: Option<usize>,
+    message: String,
+}
+
+impl Problem {
+    fn new(file: &Path, line: Option<usize>, message: impl Into<String>) -> Problem {
+        Problem {
+            file: file.to_owned(),
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file.display(), line, self.message),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+/// lints projects, gallery images, music entries and audio files without loading them into the
+/// site, printing all the problems found; returns whether everything is fine
+pub fn check(dirs: &ContentDirs) -> bool {
+    let mut problems = Vec::new();
+    check_projects(&dirs.projects_dir, &mut problems);
+    check_gallery(&dirs.gallery_dir, &mut problems);
+    check_music(&dirs.music_dir, &dirs.audio_dir, &mut problems);
+    check_audio(&dirs.audio_dir, &mut problems);
+
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("No problems found");
+        true
+    } else {
+        println!("{} problem(s) found", problems.len());
+        false
+    }
+}
+
+fn check_projects(projects_dir: &Path, problems: &mut Vec<Problem>) {
+    let mut project_dirs: Vec<PathBuf> = match projects_dir.read_dir() {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| project::is_project_dir(path))
+            .collect(),
+        Err(e) => {
+            problems.push(Problem::new(projects_dir, None, e.to_string()));
+            return;
+        }
+    };
+    project_dirs.sort();
+
+    // aliases share the namespace with slugs
+    let mut slugs: HashMap<String, PathBuf> = HashMap::new();
+    for dir in project_dirs {
+        let Some((metadata, text)) = check_project(&dir, problems) else {
+            continue;
+        };
+        let meta_path = dir.join("meta.yaml");
+        if !project::is_valid_slug(&metadata.slug) {
+            problems.push(Problem::new(
+                &meta_path,
+                key_line(&text, "slug"),
+                format!(
+                    "slug {:?} must consist of latin letters, digits, dashes and underscores",
+                    metadata.slug
+                ),
+            ));
+        }
+        let names = std::iter::once((&metadata.slug, key_line(&text, "slug"))).chain(
+            metadata
+                .aliases
+                .iter()
+                .map(|alias| (alias, line_containing(&text, alias))),
+        );
+        for (name, line) in names {
+            if let Some(other) = slugs.insert(name.clone(), meta_path.clone()) {
+                problems.push(Problem::new(
+                    &meta_path,
+                    line,
+                    format!("slug {:?} is already used in {}", name, other.display()),
+                ));
+            }
+        }
+    }
+}
+
+/// returns project metadata, if it's readable, and the raw `meta.yaml` for locating problems
+fn check_project(dir: &Path, problems: &mut Vec<Problem>) -> Option<(ProjectMetadata, String)> {
+    let body_path = dir.join("body.md");
+    match fs::read_to_string(&body_path) {
+        Ok(body) => check_media_links(dir, &body_path, &body, problems),
+        Err(e) => problems.push(Problem::new(&body_path, None, e.to_string())),
+    }
+    check_media_names(&dir.join("media"), problems);
+
+    let meta_path = dir.join("meta.yaml");
+    let text = match fs::read_to_string(&meta_path) {
+        Ok(text) => text,
+        Err(e) => {
+            problems.push(Problem::new(&meta_path, None, e.to_string()));
+            return None;
+        }
+    };
+    let metadata: ProjectMetadata = match serde_yaml::from_str(&text) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            problems.push(Problem::new(
+                &meta_path,
+                e.location().map(|l| l.line()),
+                e.to_string(),
+            ));
+            return None;
+        }
+    };
+
+    for tag in metadata.raw_tags() {
+        if let Err(e) = ProjectTag::parse(tag) {
+            problems.push(Problem::new(
+                &meta_path,
+                line_containing(&text, tag),
+                e.to_string(),
+            ));
+        }
+    }
+    for (key, date) in [
+        ("start", Some(&metadata.start)),
+        ("end", metadata.end.as_ref()),
+    ] {
+        if let Some(date) = date.filter(|d| !d.is_valid()) {
+            problems.push(Problem::new(
+                &meta_path,
+                key_line(&text, key),
+                format!("invalid {} date: {:?}", key, date),
+            ));
+        }
+    }
+    if let Some(end) = &metadata.end {
+        if end.is_valid() && metadata.start.is_valid() && is_before(end, &metadata.start) {
+            problems.push(Problem::new(
+                &meta_path,
+                key_line(&text, "end"),
+                format!("end {} is before start {}", end, metadata.start),
+            ));
+        }
+    }
+    if let Some(cover) = &metadata.cover {
+        let filename = cover.strip_prefix("media/").unwrap_or(cover);
+        if !dir.join("media").join(filename).is_file() {
+            problems.push(Problem::new(
+                &meta_path,
+                key_line(&text, "cover"),
+                format!("cover {:?} is not in the project media", cover),
+            ));
+        }
+    }
+    Some((metadata, text))
+}
+
+/// partial dates are compared up to the precision they share, e.g. 2023 is not before may 2023
+fn is_before(date: &Date, other: &Date) -> bool {
+    let truncated = |d: &Date| {
+        let month = d
+            .month
+            .filter(|_| date.month.is_some() && other.month.is_some());
+        let day = d
+            .day
+            .filter(|_| month.is_some() && date.day.is_some() && other.day.is_some());
+        (d.year, month, day)
+    };
+    truncated(date) < truncated(other)
+}
+
+/// links to the project's media dir, both in Markdown and raw HTML, must lead to existing files
+fn check_media_links(dir: &Path, body_path: &Path, body: &str, problems: &mut Vec<Problem>) {
+    let arena = Arena::new();
+    let root = comrak::parse_document(&arena, body, &project::markdown_options());
+    let html_media_re = Regex::new("(?:src|href)=\"(media/[^\"]*)\"").unwrap();
+    let html_refs = |html: &str, line: usize| -> Vec<(usize, String)> {
+        html_media_re
+            .captures_iter(html)
+            .map(|caps| {
+                let m = caps.get(1).unwrap();
+                (
+                    line + html[..m.start()].matches('\n').count(),
+                    m.as_str().to_owned(),
+                )
+            })
+            .collect()
+    };
+    for node in root.descendants() {
+        let data = node.data();
+        let line = data.sourcepos.start.line;
+        let refs = match &data.value {
+            NodeValue::Image(link) | NodeValue::Link(link) => vec![(line, link.url.clone())],
+            NodeValue::HtmlInline(html) => html_refs(html, line),
+            NodeValue::HtmlBlock(block) => html_refs(&block.literal, line),
+            _ => Vec::new(),
+        };
+        for (line, url) in refs {
+            let Some(path) = url.strip_prefix("media/") else {
+                continue;
+            };
+            let path = path.split(['#', '?']).next().unwrap_or_default();
+            if !dir.join("media").join(path).is_file() {
+                problems.push(Problem::new(
+                    body_path,
+                    Some(line),
+                    format!("broken media link {:?}", url),
+                ));
+            }
+        }
+    }
+}
+
+/// media files and their optimized versions must not overwrite each other, even on
+/// case-insensitive filesystems
+fn check_media_names(media_dir: &Path, problems: &mut Vec<Problem>) {
+    let Ok(entries) = media_dir.read_dir() else {
+        return;
+    };
+    let mut filenames: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|filename| !filename.starts_with('.'))
+        .collect();
+    filenames.sort();
+
+    let mut produced_by: HashMap<String, String> = HashMap::new();
+    for filename in filenames {
+        let mut conflicting: Vec<String> = Vec::new();
+        for name in projectmedia::synced_names(&filename) {
+            match produced_by.insert(name.to_lowercase(), filename.clone()) {
+                Some(other) if other != filename && !conflicting.contains(&other) => {
+                    problems.push(Problem::new(
+                        &media_dir.join(&filename),
+                        None,
+                        format!("conflicts with {:?}, both are served as {:?}", other, name),
+                    ));
+                    conflicting.push(other);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn check_gallery(gallery_dir: &Path, problems: &mut Vec<Problem>) {
+    let mut sources = match gallery::sources(gallery_dir) {
+        Ok(sources) => sources,
+        Err(e) => {
+            problems.push(Problem::new(gallery_dir, None, e.to_string()));
+            return;
+        }
+    };
+    sources.sort();
+    let filenames: HashSet<String> = sources
+        .iter()
+        .filter_map(|path| path.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect();
+    check_albums(gallery_dir, &filenames, problems);
+    for path in sources {
+        if let Err(e) = image::image_dimensions(&path) {
+            problems.push(Problem::new(
+                &path,
+                None,
+                format!("unreadable image: {}", e),
+            ));
+        } else if let Err(e) = gallery::read_exif(&path) {
+            let message = match e {
+                GalleryError::Exif { source, .. } => {
+                    format!("unreadable EXIF metadata: {}", source)
+                }
+                GalleryError::MissingExifField { field, .. } => {
+                    format!("EXIF metadata misses {} field", field)
+                }
+                GalleryError::InvalidExifField { field, source, .. } => {
+                    format!("invalid EXIF {} field: {}", field, source)
+                }
+                e => e.to_string(),
+            };
+            problems.push(Problem::new(&path, None, message));
+        }
+    }
+}
+
+/// albums must refer to the images in the gallery dir
+fn check_albums(gallery_dir: &Path, filenames: &HashSet<String>, problems: &mut Vec<Problem>) {
+    let path = gallery_dir.join(ALBUMS_FILENAME);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            problems.push(Problem::new(&path, None, e.to_string()));
+            return;
+        }
+    };
+    let albums = match galleryalbums::parse(&text, &path) {
+        Ok(albums) => albums,
+        Err(GalleryError::Albums { source, .. }) => {
+            problems.push(Problem::new(
+                &path,
+                source.location().map(|l| l.line()),
+                source.to_string(),
+            ));
+            return;
+        }
+        Err(e) => {
+            let line = match &e {
+                GalleryError::InvalidAlbumSlug { slug }
+                | GalleryError::DuplicateAlbumSlug { slug } => {
+                    line_containing(&text, &format!("slug: {}", slug))
+                }
+                _ => None,
+            };
+            problems.push(Problem::new(&path, line, e.to_string()));
+            return;
+        }
+    };
+    for album in albums.iter() {
+        for filename in album.images.iter() {
+            if !filenames.contains(filename) {
+                problems.push(Problem::new(
+                    &path,
+                    line_containing(&text, filename),
+                    format!(
+                        "album {:?} refers to {:?}, which is not in the gallery",
+                        album.slug, filename
+                    ),
+                ));
+            }
+        }
+        if let Some(cover) = &album.cover {
+            if !album.images.contains(cover) {
+                problems.push(Problem::new(
+                    &path,
+                    line_containing(&text, &format!("cover: {}", cover)),
+                    format!(
+                        "cover {:?} of album {:?} is not one of its images",
+                        cover, album.slug
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+fn check_music(music_dir: &Path, audio_dir: &Path, problems: &mut Vec<Problem>) {
+    let mut paths: Vec<PathBuf> = match music_dir.read_dir() {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| music::is_music_file(path))
+            .collect(),
+        Err(e) => {
+            problems.push(Problem::new(music_dir, None, e.to_string()));
+            return;
+        }
+    };
+    paths.sort();
+    for path in paths {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                problems.push(Problem::new(&path, None, e.to_string()));
+                continue;
+            }
+        };
+        let entry: MusicEntry = match serde_yaml::from_str(&text) {
+            Ok(entry) => entry,
+            Err(e) => {
+                problems.push(Problem::new(
+                    &path,
+                    e.location().map(|l| l.line()),
+                    e.to_string(),
+                ));
+                continue;
+            }
+        };
+        for recording in entry.recordings.iter() {
+            if !audio_dir.join(&recording.file).is_file() {
+                problems.push(Problem::new(
+                    &path,
+                    line_containing(&text, &recording.file),
+                    format!("audio file {:?} is not in the audio dir", recording.file),
+                ));
+            }
+        }
+    }
+}
+
+fn check_audio(audio_dir: &Path, problems: &mut Vec<Problem>) {
+    // the site has no tracks without it, which is fine
+    if !audio_dir.is_dir() {
+        return;
+    }
+    let relatives = match audio::list(audio_dir) {
+        Ok(relatives) => relatives,
+        Err(e) => {
+            problems.push(Problem::new(audio_dir, None, e.to_string()));
+            return;
+        }
+    };
+    for relative in relatives {
+        let path = audio_dir.join(&relative);
+        if let Err(e) = audio::track_slug(&path, &relative) {
+            problems.push(Problem::new(&path, None, e.to_string()));
+        }
+    }
+}
+
+fn line_containing(text: &str, needle: &str) -> Option<usize> {
+    text.lines().position(|l| l.contains(needle)).map(|i| i + 1)
+}
+
+fn key_line(text: &str, key: &str) -> Option<usize> {
+    let prefix = format!("{}:", key);
+    text.lines()
+        .position(|l| l.starts_with(&prefix))
+        .map(|i| i + 1)
+}
@@ -1,8 +1,9 @@
 use itertools::Itertools;
 use palette_extract::{get_palette_with_options, MaxColors, PixelEncoding, PixelFilter, Quality};
+use serde::Serialize;
 
 #[allow(dead_code)]
-#[derive(PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize)]
 pub enum PaletteExtractionAlgorithm {
     MedianCut = 0,
     MeanCut = 1,
@@ -31,7 +32,7 @@ pub fn extract_palette(
         );
     }
 
-    if values.len() == 0 {
+    if values.is_empty() {
         return None;
     }
     if values.len() == 1 {
@@ -41,11 +42,7 @@ pub fn extract_palette(
         let mean = values
             .iter()
             .fold([0; 3], |a, b| {
-                [
-                    a[0] as u32 + b[0] as u32,
-                    a[1] as u32 + b[1] as u32,
-                    a[2] as u32 + b[2] as u32,
-                ]
+                [a[0] + b[0] as u32, a[1] + b[1] as u32, a[2] + b[2] as u32]
             })
             .map(|v| v / (values.len() as u32));
         return Some(vec![[mean[0] as u8, mean[1] as u8, mean[2] as u8]]);
@@ -59,10 +56,7 @@ pub fn extract_palette(
         });
     }
 
-    let split_by = match ranges.iter().position_max() {
-        Some(i) => i,
-        None => return None,
-    };
+    let split_by = ranges.iter().position_max()?;
 
     values.sort_by_key(|v| v[split_by]);
 
@@ -74,8 +68,7 @@ pub fn extract_palette(
             values
                 .iter()
                 .enumerate()
-                .filter(|(_, v)| v[split_by] > mean)
-                .next()
+                .find(|(_, v)| v[split_by] > mean)
                 .unwrap()
                 .0
         }
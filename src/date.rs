@@ -1,6 +1,7 @@
-use serde::Deserialize;
+use jiff::{civil, tz::TimeZone, Timestamp};
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct Date {
     pub year: u16,
     pub month: Option<u16>,
@@ -38,6 +39,38 @@ impl std::fmt::Display for Date {
     }
 }
 
+impl Date {
+    /// the first day of the (possibly partial) date; invalid month/day are treated as unset
+    pub fn to_civil(&self) -> civil::Date {
+        let month = self.month.unwrap_or(1) as i8;
+        let day = self.day.unwrap_or(1) as i8;
+        civil::Date::new(self.year as i16, month, day)
+            .or_else(|_| civil::Date::new(self.year as i16, month, 1))
+            .or_else(|_| civil::Date::new(self.year as i16, 1, 1))
+            .unwrap_or_default()
+    }
+
+    /// whether month and day, if set, exist in the calendar
+    pub fn is_valid(&self) -> bool {
+        match (self.month, self.day) {
+            (None, None) => true,
+            (Some(month), None) => (1..=12).contains(&month),
+            (Some(month), Some(day)) => match (i8::try_from(month), i8::try_from(day)) {
+                (Ok(month), Ok(day)) => civil::Date::new(self.year as i16, month, day).is_ok(),
+                _ => false,
+            },
+            (None, Some(_)) => false,
+        }
+    }
+
+    pub fn to_timestamp(&self) -> Timestamp {
+        self.to_civil()
+            .to_zoned(TimeZone::UTC)
+            .map(|z| z.timestamp())
+            .unwrap_or_default()
+    }
+}
+
 impl std::cmp::Ord for Date {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         let (cmp_set_unset, cmp_unset_set) =
@@ -52,32 +85,32 @@ impl std::cmp::Ord for Date {
                 }
                 if let Some(self_day) = self.day {
                     if let Some(other_day) = other.day {
-                        return self_day.cmp(&other_day);
+                        self_day.cmp(&other_day)
                     } else {
-                        return cmp_set_unset;
+                        cmp_set_unset
                     }
                 } else {
-                    return if other.day.is_none() {
+                    if other.day.is_none() {
                         std::cmp::Ordering::Equal
                     } else {
                         cmp_unset_set
-                    };
+                    }
                 }
             } else {
-                return cmp_set_unset;
+                cmp_set_unset
             }
         } else {
-            return if other.month.is_none() {
+            if other.month.is_none() {
                 std::cmp::Ordering::Equal
             } else {
                 cmp_unset_set
-            };
+            }
         }
     }
 }
 
 impl std::cmp::PartialOrd for Date {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        return Some(self.cmp(other));
+        Some(self.cmp(other))
     }
 }
@@ -0,0 +1,261 @@
+use askama::Template;
+use axum::{
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+
+use crate::project::{ProjectCatalog, ProjectError, ProjectTag, TagFilter};
+
+/// candidates less similar than that are not worth suggesting
+const SUGGESTION_THRESHOLD: f64 = 0.6;
+const MAX_SUGGESTIONS: usize = 3;
+
+#[derive(Debug)]
+pub struct Suggestion {
+    pub href: String,
+    pub title: String,
+}
+
+/// HTML page for an error status, with an optional explanation and links to the pages the
+/// visitor might have meant
+#[derive(Debug)]
+pub struct ErrorPage {
+    pub status: StatusCode,
+    pub message: Option<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate<'a> {
+    page: &'a ErrorPage,
+}
+
+impl ErrorPage {
+    pub fn new(status: StatusCode) -> ErrorPage {
+        ErrorPage {
+            status,
+            message: None,
+            suggestions: Vec::new(),
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> ErrorPage {
+        ErrorPage::new(StatusCode::NOT_FOUND).with_message(message)
+    }
+
+    pub fn with_message(self, message: impl Into<String>) -> ErrorPage {
+        ErrorPage {
+            message: Some(message.into()),
+            ..self
+        }
+    }
+
+    pub fn with_suggestions(self, suggestions: Vec<Suggestion>) -> ErrorPage {
+        ErrorPage {
+            suggestions,
+            ..self
+        }
+    }
+
+    /// tag filter from the query, or an error page suggesting similar existing tags: 400 for a
+    /// tag that fails to parse, 404 for a required tag no published project has; excluding
+    /// such a tag changes nothing, so it is let through
+    pub fn tag_filter(
+        params: &[(String, String)],
+        catalog: &ProjectCatalog,
+    ) -> Result<TagFilter, ErrorPage> {
+        let tags: Vec<(String, ProjectTag)> = catalog
+            .tag_groups()
+            .into_iter()
+            .flat_map(|(_, tags)| tags)
+            .map(|t| (t.to_string(), t))
+            .collect();
+        let suggest = |matches: Vec<&str>| -> Vec<Suggestion> {
+            matches
+                .into_iter()
+                .filter_map(|s| tags.iter().find(|(t, _)| t == s))
+                .map(|(title, tag)| Suggestion {
+                    href: format!("/projects?{}", TagFilter::default().with_all(tag).query()),
+                    title: title.clone(),
+                })
+                .collect()
+        };
+
+        let filter = TagFilter::from_query(params).map_err(|error| {
+            let page = ErrorPage::new(StatusCode::BAD_REQUEST).with_message(error.to_string());
+            let ProjectError::InvalidTag { tag } = error else {
+                return page;
+            };
+            // a tag without the category is likely meant to be just its name
+            let names = tags.iter().map(|(t, tag)| (tag.name.as_str(), t.as_str()));
+            page.with_suggestions(suggest(closest(tag.trim_start_matches('-'), names)))
+        })?;
+
+        let unknown = filter
+            .all
+            .iter()
+            .chain(filter.any.iter())
+            .find(|tag| !tags.iter().any(|(_, t)| t == *tag));
+        if let Some(tag) = unknown {
+            let candidates = tags.iter().map(|(t, _)| (t.as_str(), t.as_str()));
+            return Err(ErrorPage::not_found(format!(
+                "there are no projects tagged {:?}",
+                tag.to_string()
+            ))
+            .with_suggestions(suggest(closest(&tag.to_string(), candidates))));
+        }
+        Ok(filter)
+    }
+
+    fn reason(&self) -> String {
+        self.status
+            .canonical_reason()
+            .unwrap_or("error")
+            .to_lowercase()
+    }
+}
+
+impl IntoResponse for ErrorPage {
+    fn into_response(self) -> Response {
+        match (ErrorTemplate { page: &self }).render() {
+            Ok(html) => (self.status, Html(html)).into_response(),
+            Err(e) => {
+                tracing::error!("Failed to render error page: {}", e);
+                self.status.into_response()
+            }
+        }
+    }
+}
+
+/// `(key, value)` candidates with keys most similar to `query`, best first
+pub fn closest<'a>(
+    query: &str,
+    candidates: impl Iterator<Item = (&'a str, &'a str)>,
+) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    let mut scored: Vec<(f64, &str)> = candidates
+        .map(|(key, value)| {
+            (
+                strsim::normalized_damerau_levenshtein(&query, &key.to_lowercase()),
+                value,
+            )
+        })
+        .filter(|(score, _)| *score >= SUGGESTION_THRESHOLD)
+        .collect();
+    scored.sort_by(|(s1, _), (s2, _)| s2.total_cmp(s1));
+    let mut values: Vec<&str> = Vec::new();
+    for (_, value) in scored {
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    }
+    values.truncate(MAX_SUGGESTIONS);
+    values
+}
+
+/// renders error pages in place of bodyless error responses, e.g. from the static file
+/// services or failed template rendering
+pub async fn render_bare_errors(response: Response) -> Response {
+    let status = response.status();
+    let is_bare = !response.headers().contains_key(header::CONTENT_TYPE);
+    if is_bare && (status.is_client_error() || status.is_server_error()) {
+        ErrorPage::new(status).into_response()
+    } else {
+        response
+    }
+}
+
+/// handler for the URLs not matching any route
+pub async fn not_found() -> ErrorPage {
+    ErrorPage::not_found("there is no such page")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::fs;
+
+    use super::*;
+    use crate::project::Project;
+
+    fn catalog(dir: &std::path::Path, projects: &[(&str, &[&str])]) -> ProjectCatalog {
+        let media_dir = dir.join("media");
+        let projects = projects
+            .iter()
+            .map(|(slug, tags)| {
+                let project_dir = dir.join(slug);
+                fs::create_dir_all(&project_dir).unwrap();
+                let tags: String = tags.iter().map(|t| format!("\n  - {}", t)).collect();
+                fs::write(
+                    project_dir.join("meta.yaml"),
+                    format!("title: {slug}\nslug: {slug}\nstart:\n  year: 2024\ntags:{tags}\n"),
+                )
+                .unwrap();
+                fs::write(project_dir.join("body.md"), "body").unwrap();
+                Project::load(&project_dir, &media_dir, &HashSet::new()).unwrap()
+            })
+            .collect();
+        ProjectCatalog::from_projects(projects).unwrap()
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn test_catalog() -> (tempfile::TempDir, ProjectCatalog) {
+        let dir = tempfile::tempdir().unwrap();
+        let catalog = catalog(
+            dir.path(),
+            &[
+                ("one", &["code:rust", "platform:web"]),
+                ("two", &["code:python", "platform:terminal"]),
+            ],
+        );
+        (dir, catalog)
+    }
+
+    #[test]
+    fn known_tags_pass() {
+        let (_dir, catalog) = test_catalog();
+        let filter = ErrorPage::tag_filter(
+            &params(&[("tag", "code:rust"), ("any", "platform:terminal")]),
+            &catalog,
+        )
+        .unwrap();
+        assert_eq!(filter.all.len(), 1);
+        assert_eq!(filter.any.len(), 1);
+        // no project matches both, which is an empty listing rather than an error
+        assert!(!catalog.published().any(|p| filter.matches(p)));
+    }
+
+    #[test]
+    fn unknown_excluded_tag_is_ignored() {
+        let (_dir, catalog) = test_catalog();
+        let filter = ErrorPage::tag_filter(&params(&[("tag", "-code:cobol")]), &catalog).unwrap();
+        assert!(catalog.published().all(|p| filter.matches(p)));
+    }
+
+    #[test]
+    fn unknown_required_tag_is_not_found() {
+        let (_dir, catalog) = test_catalog();
+        for key in ["tag", "any"] {
+            let page = ErrorPage::tag_filter(&params(&[(key, "code:rsut")]), &catalog).unwrap_err();
+            assert_eq!(page.status, StatusCode::NOT_FOUND);
+            assert_eq!(page.suggestions.len(), 1);
+            assert_eq!(page.suggestions[0].title, "code:rust");
+            assert_eq!(page.suggestions[0].href, "/projects?tag=code%3Arust");
+        }
+    }
+
+    #[test]
+    fn tag_without_category_is_bad_request() {
+        let (_dir, catalog) = test_catalog();
+        let page = ErrorPage::tag_filter(&params(&[("tag", "pyhton")]), &catalog).unwrap_err();
+        assert_eq!(page.status, StatusCode::BAD_REQUEST);
+        assert_eq!(page.suggestions[0].title, "code:python");
+    }
+}
@@ -0,0 +1,517 @@
+use axum::{body::Body, http::Request, Router};
+use percent_encoding::percent_decode_str;
+use regex::{Captures, Regex};
+use std::{
+    collections::{HashSet, VecDeque},
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tower::ServiceExt;
+
+use crate::gallery;
+use crate::galleryexif::Equipment;
+use crate::imaging;
+use crate::project::TagFilter;
+use crate::{AppState, ContentDirs};
+
+/// URL prefixes served from directories; they are copied as-is instead of being crawled
+const STATIC_TREES: [&str; 9] = [
+    "/static/",
+    "/projects/media/",
+    "/projects/cards/",
+    "/gallery/thumbnails/",
+    "/gallery/media/",
+    "/gallery/variants/",
+    "/gallery/full/",
+    "/music/tracks/media/",
+    "/audio/",
+];
+
+/// pages that only make sense when served dynamically; links to them lead to the live site
+const DYNAMIC_PAGES: [&str; 1] = ["/search"];
+
+/// renders every page of the site into `out_dir`, rewriting internal links to be relative
+/// so that the result can be hosted anywhere (or just opened from disk)
+pub async fn export(
+    app: Router,
+    state: &AppState,
+    dirs: &ContentDirs,
+    out_dir: &Path,
+) -> io::Result<()> {
+    tracing::info!("Exporting static site into {:?}", out_dir);
+    fs::create_dir_all(out_dir)?;
+
+    // generated files are kept in the static dir, but served under their own URLs; transcoded
+    // audio is not exported at all, static hosting can only serve the originals
+    let generated = [
+        &dirs.project_media_dir,
+        &dirs.gallery_thumbnails_dir,
+        &dirs.gallery_stdmedia_dir,
+        &dirs.gallery_variants_dir,
+        &dirs.audio_media_dir,
+        &dirs.audio_cache_dir,
+    ];
+    copy_tree(&dirs.static_dir, &out_dir.join("static"), &generated)?;
+    // media of unpublished projects is only served with the preview token
+    let catalog = state.project_catalog.load();
+    for project in catalog.published().filter(|p| p.media_dir.is_dir()) {
+        copy_tree(
+            &project.media_dir,
+            &out_dir.join("projects/media").join(&project.metadata.slug),
+            &[],
+        )?;
+    }
+    copy_tree(
+        &dirs.gallery_thumbnails_dir,
+        &out_dir.join("gallery/thumbnails"),
+        &[],
+    )?;
+    copy_tree(
+        &dirs.gallery_stdmedia_dir,
+        &out_dir.join("gallery/media"),
+        &[],
+    )?;
+    copy_tree(
+        &dirs.gallery_variants_dir,
+        &out_dir.join("gallery/variants"),
+        &[],
+    )?;
+    copy_gallery_originals(&dirs.gallery_dir, &out_dir.join("gallery/full"))?;
+    copy_tree(&dirs.audio_dir, &out_dir.join("audio"), &[])?;
+    copy_tree(
+        &dirs.audio_media_dir,
+        &out_dir.join("music/tracks/media"),
+        &[],
+    )?;
+
+    // everything is reachable by links from the index page, but we list all the known pages
+    // explicitly so that nothing is missed
+    let mut queue: VecDeque<String> = VecDeque::new();
+    for url in [
+        "/",
+        "/projects",
+        "/projects/feed.xml",
+        "/projects/rss.xml",
+        "/tags",
+        "/music",
+        "/music/tracks",
+        "/gallery",
+        "/gallery/feed.xml",
+        "/gallery/rss.xml",
+        "/sitemap.xml",
+        "/robots.txt",
+    ] {
+        queue.push_back(url.to_owned());
+    }
+    {
+        let catalog = state.project_catalog.load();
+        for project in catalog.published() {
+            queue.push_back(format!("/projects/{}", project.metadata.slug));
+            // only linked from the metadata, which is not crawled
+            if project.social_card.is_some() {
+                queue.push_back(project.social_image.url.clone());
+            }
+        }
+        for (_, tags) in catalog.tag_groups().iter() {
+            for tag in tags {
+                let query = TagFilter::default().with_all(tag).query();
+                queue.push_back(format!("/projects?{}", query));
+                queue.push_back(format!("/projects/feed.xml?{}", query));
+                queue.push_back(format!("/projects/rss.xml?{}", query));
+            }
+        }
+        for track in state.audio_catalog.load().tracks.iter() {
+            queue.push_back(format!("/music/tracks/{}", track.slug));
+        }
+        let gallery = state.gallery.load();
+        for page in 1..=gallery.total_pages(crate::GALLERY_PAGE_SIZE) {
+            queue.push_back(format!("/gallery?p={}", page));
+        }
+        for image in gallery.images.iter() {
+            queue.push_back(format!(
+                "/gallery/{}",
+                imaging::escape_url_path(&image.filename)
+            ));
+        }
+        let equipment = Equipment::count(&gallery.images);
+        for item in equipment.cameras.iter().chain(equipment.lenses.iter()) {
+            queue.push_back(item.url.clone());
+        }
+        queue.push_back("/gallery/albums".to_owned());
+        for album in gallery.albums.iter() {
+            queue.push_back(format!("/gallery/albums/{}", album.slug));
+        }
+    }
+
+    let attr_re = Regex::new("(href|src|action|srcset)=\"([^\"]*)\"").unwrap();
+    let mut visited: HashSet<String> = HashSet::new();
+    while let Some(url) = queue.pop_front() {
+        let Some(file) = export_path(&url) else {
+            continue;
+        };
+        if !visited.insert(file.clone()) {
+            continue;
+        }
+
+        let request = match Request::get(canonical_url(&url)).body(Body::empty()) {
+            Ok(request) => request,
+            Err(e) => {
+                tracing::warn!("Skipping {}: {}", url, e);
+                continue;
+            }
+        };
+        let response = app
+            .clone()
+            .oneshot(request)
+            .await
+            .map_err(io::Error::other)?;
+        if !response.status().is_success() {
+            tracing::warn!("Skipping {}: {}", url, response.status());
+            continue;
+        }
+        let is_html = response
+            .headers()
+            .get(axum::http::header::CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .is_some_and(|ct| ct.starts_with("text/html"));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .map_err(io::Error::other)?;
+
+        let contents = if is_html {
+            let html = String::from_utf8_lossy(&body);
+            attr_re
+                .replace_all(&html, |caps: &Captures| {
+                    let value = caps[2].replace("&amp;", "&");
+                    let mut rewrite = |href: &str| match resolve(&url, href) {
+                        None => href.to_owned(),
+                        Some((target, fragment)) => match export_path(&target) {
+                            Some(target_file) => {
+                                if !is_static(&target) {
+                                    queue.push_back(target);
+                                }
+                                imaging::escape_url_path(&relative_path(&file, &target_file))
+                                    + &fragment
+                            }
+                            // dynamic pages can't be exported, so we link to the live site
+                            None => format!("{}{}{}", state.base_url, target, fragment),
+                        },
+                    };
+                    let rewritten = if &caps[1] == "srcset" {
+                        // comma-separated list of "<url> <descriptor>" candidates
+                        value
+                            .split(',')
+                            .map(|candidate| {
+                                let candidate = candidate.trim();
+                                match candidate.split_once(' ') {
+                                    Some((href, descriptor)) => {
+                                        format!("{} {}", rewrite(href), descriptor)
+                                    }
+                                    None => rewrite(candidate),
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    } else {
+                        rewrite(&value)
+                    };
+                    format!("{}=\"{}\"", &caps[1], rewritten.replace('&', "&amp;"))
+                })
+                .into_owned()
+                .into_bytes()
+        } else {
+            body.to_vec()
+        };
+
+        let target = out_dir.join(&file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&target, contents)?;
+        tracing::debug!("Exported {} as {}", url, file);
+    }
+    tracing::info!("Exported {} pages into {:?}", visited.len(), out_dir);
+    Ok(())
+}
+
+/// copies `src` into `dst`, except for hidden files and the `skip` subdirs
+fn copy_tree(src: &Path, dst: &Path, skip: &[&PathBuf]) -> io::Result<()> {
+    if !src.is_dir() {
+        tracing::warn!("Not copying {:?}: not a directory", src);
+        return Ok(());
+    }
+    fs::create_dir_all(dst)?;
+    for entry in src.read_dir()?.flatten() {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        if skip.contains(&&path) {
+            continue;
+        }
+        if path.is_dir() {
+            copy_tree(&path, &dst.join(entry.file_name()), skip)?;
+        } else {
+            fs::copy(&path, dst.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+/// only the images are published, not the albums file next to them
+fn copy_gallery_originals(src: &Path, dst: &Path) -> io::Result<()> {
+    let sources = match gallery::sources(src) {
+        Ok(sources) => sources,
+        Err(e) => {
+            tracing::warn!("Not copying gallery originals: {}", e);
+            return Ok(());
+        }
+    };
+    fs::create_dir_all(dst)?;
+    for path in sources {
+        if let Some(filename) = path.file_name() {
+            fs::copy(&path, dst.join(filename))?;
+        }
+    }
+    Ok(())
+}
+
+fn is_static(url: &str) -> bool {
+    STATIC_TREES.iter().any(|prefix| url.starts_with(prefix))
+}
+
+/// resolves a link found on the page at `page_url` into an absolute URL (with query, without
+/// fragment) and the fragment; returns `None` for links leading outside the site
+fn resolve(page_url: &str, href: &str) -> Option<(String, String)> {
+    if href.is_empty() || href.starts_with('#') || href.starts_with("//") {
+        return None;
+    }
+    // links with a scheme (https:, mailto:, etc) lead outside
+    if href
+        .split(['/', '?'])
+        .next()
+        .is_some_and(|s| s.contains(':'))
+    {
+        return None;
+    }
+    let (href, fragment) = match href.split_once('#') {
+        Some((href, fragment)) => (href, format!("#{}", fragment)),
+        None => (href, String::new()),
+    };
+    let page_path = page_url.split('?').next().unwrap_or("/");
+    let resolved = if href.starts_with('/') {
+        href.to_owned()
+    } else if href.starts_with('?') {
+        format!("{}{}", page_path, href)
+    } else {
+        let base_dir = &page_path[..page_path.rfind('/').map_or(0, |i| i + 1)];
+        format!("{}{}", base_dir, href)
+    };
+
+    // normalizing dot segments
+    let (path, query) = match resolved.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (resolved.as_str(), None),
+    };
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/').skip(1) {
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s),
+        }
+    }
+    let mut normalized = format!("/{}", segments.join("/"));
+    if let Some(query) = query {
+        normalized = format!("{}?{}", normalized, query);
+    }
+    Some((normalized, fragment))
+}
+
+/// links may or may not have their paths percent-encoded; this encodes them uniformly, so that
+/// they are valid request URIs
+fn canonical_url(url: &str) -> String {
+    match url.split_once('?') {
+        Some((path, query)) => format!("{}?{}", canonical_url(path), query),
+        None => imaging::escape_url_path(&percent_decode_str(url).decode_utf8_lossy()),
+    }
+}
+
+/// maps a site URL into a file path inside the export directory; returns `None` for URLs that
+/// can't be represented by a static file
+fn export_path(url: &str) -> Option<String> {
+    let (path, query) = match url.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (url, None),
+    };
+    // files are named after the decoded paths, which is what static hosting looks up
+    let path = percent_decode_str(path).decode_utf8_lossy();
+    let path = path.as_ref();
+    if is_static(path) {
+        return query
+            .is_none()
+            .then(|| path.trim_start_matches('/').to_owned());
+    }
+    if DYNAMIC_PAGES.contains(&path.trim_end_matches('/')) {
+        return None;
+    }
+
+    let path = path.trim_matches('/');
+    // pages are exported as directories with an index file, except for those already looking
+    // like files, e.g. feeds
+    let (dir, filename) = match path.rsplit_once('/').unwrap_or(("", path)) {
+        (dir, filename) if filename.ends_with(".xml") || filename.ends_with(".txt") => {
+            (dir.to_owned(), filename)
+        }
+        _ => (path.to_owned(), "index.html"),
+    };
+    // only single-tag filters get their own pages, combinations are left to the live site
+    let params: Vec<(String, String)> = query
+        .map(|q| form_urlencoded::parse(q.as_bytes()).into_owned().collect())
+        .unwrap_or_default();
+    let dir = match params
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] => dir,
+        [("tag", tag)] if !tag.starts_with('-') => {
+            format!("{}/tag/{}", dir, slugify::slugify(tag, "", "-", None))
+        }
+        [("album", album)] => format!("{}/album/{}", dir, album),
+        // as well as the first pages of single camera or lens filters
+        [(key @ ("camera" | "lens"), value)] => {
+            format!("{}/{}/{}", dir, key, slugify::slugify(value, "", "-", None))
+        }
+        [("p", "1")] => dir,
+        [("p", page)] if page.parse::<usize>().is_ok() => {
+            format!("{}/page/{}", dir, page)
+        }
+        _ => return None,
+    };
+    Some(
+        format!("{}/{}", dir, filename)
+            .trim_start_matches('/')
+            .to_owned(),
+    )
+}
+
+/// relative link from one exported file to another
+fn relative_path(from_file: &str, to_file: &str) -> String {
+    let from_dir: Vec<&str> = from_file.split('/').collect();
+    let from_dir = &from_dir[..from_dir.len() - 1];
+    let to: Vec<&str> = to_file.split('/').collect();
+    let common = from_dir
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut parts: Vec<&str> = vec![".."; from_dir.len() - common];
+    parts.extend(&to[common..]);
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_absolute_and_relative_links() {
+        let page = "/projects/some-project";
+        assert_eq!(
+            resolve(page, "/gallery?p=2"),
+            Some(("/gallery?p=2".to_owned(), String::new()))
+        );
+        assert_eq!(
+            resolve(page, "other#section"),
+            Some(("/projects/other".to_owned(), "#section".to_owned()))
+        );
+        assert_eq!(
+            resolve(page, "../gallery/./img.jpg"),
+            Some(("/gallery/img.jpg".to_owned(), String::new()))
+        );
+        assert_eq!(
+            resolve("/projects?tag=code%3Arust", "?tag=code%3Ac"),
+            Some(("/projects?tag=code%3Ac".to_owned(), String::new()))
+        );
+    }
+
+    #[test]
+    fn resolve_skips_external_links() {
+        for href in [
+            "",
+            "#footnote",
+            "//example.com/x",
+            "https://example.com",
+            "mailto:me@example.com",
+        ] {
+            assert_eq!(resolve("/", href), None, "{:?}", href);
+        }
+    }
+
+    #[test]
+    fn export_path_pages_and_files() {
+        assert_eq!(export_path("/").as_deref(), Some("index.html"));
+        assert_eq!(
+            export_path("/projects/x").as_deref(),
+            Some("projects/x/index.html")
+        );
+        assert_eq!(
+            export_path("/projects/feed.xml").as_deref(),
+            Some("projects/feed.xml")
+        );
+        assert_eq!(export_path("/robots.txt").as_deref(), Some("robots.txt"));
+        assert_eq!(
+            export_path("/gallery/IMG%200001.jpg").as_deref(),
+            Some("gallery/IMG 0001.jpg/index.html")
+        );
+        assert_eq!(
+            export_path("/gallery/media/IMG%200001.jpg").as_deref(),
+            Some("gallery/media/IMG 0001.jpg")
+        );
+        assert_eq!(export_path("/gallery/media/a.jpg?x=1"), None);
+        assert_eq!(export_path("/search?q=rust"), None);
+    }
+
+    #[test]
+    fn export_path_query_variants() {
+        let tag = TagFilter::default()
+            .with_all(&crate::project::ProjectTag::parse("code:c++").unwrap())
+            .query();
+        assert_eq!(
+            export_path(&format!("/projects?{}", tag)).as_deref(),
+            Some("projects/tag/code-c/index.html")
+        );
+        assert_eq!(
+            export_path(&format!("/projects/feed.xml?{}", tag)).as_deref(),
+            Some("projects/tag/code-c/feed.xml")
+        );
+        assert_eq!(export_path("/projects?tag=-code%3Arust"), None);
+        assert_eq!(export_path("/projects?tag=code%3Arust&any=code%3Ac"), None);
+        assert_eq!(
+            export_path("/gallery?camera=Canon+PowerShot+G11").as_deref(),
+            Some("gallery/camera/canon-powershot-g11/index.html")
+        );
+        assert_eq!(
+            export_path("/gallery?lens=EF%2050mm%20f%2F1.8").as_deref(),
+            Some("gallery/lens/ef-50mm-f-1-8/index.html")
+        );
+        assert_eq!(
+            export_path("/gallery/img.jpg?album=trip").as_deref(),
+            Some("gallery/img.jpg/album/trip/index.html")
+        );
+        assert_eq!(
+            export_path("/gallery?p=1").as_deref(),
+            Some("gallery/index.html")
+        );
+        assert_eq!(
+            export_path("/gallery?p=3").as_deref(),
+            Some("gallery/page/3/index.html")
+        );
+        assert_eq!(export_path("/gallery?p=last"), None);
+        assert_eq!(export_path("/gallery?camera=x&p=2"), None);
+    }
+}
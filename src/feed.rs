@@ -0,0 +1,194 @@
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use jiff::{tz::TimeZone, Timestamp};
+
+use crate::{imaging, project::TagFilter, AppState};
+
+const GALLERY_FEED_SIZE: usize = 50;
+
+pub struct Enclosure {
+    pub url: String,
+    pub mime_type: &'static str,
+    pub length: u64,
+}
+
+pub struct FeedEntry {
+    pub title: String,
+    pub url: String,
+    pub updated: Timestamp,
+    pub content_html: Option<String>,
+    pub enclosure: Option<Enclosure>,
+}
+
+impl FeedEntry {
+    pub fn pub_date(&self) -> String {
+        rfc2822(&self.updated)
+    }
+}
+
+pub struct Feed {
+    pub title: String,
+    /// the feed's own URL
+    pub url: String,
+    /// URL of the HTML page the feed mirrors
+    pub page_url: String,
+    pub entries: Vec<FeedEntry>,
+}
+
+impl Feed {
+    pub fn updated(&self) -> Timestamp {
+        self.entries
+            .iter()
+            .map(|e| e.updated)
+            .max()
+            .unwrap_or_default()
+    }
+
+    pub fn pub_date(&self) -> String {
+        rfc2822(&self.updated())
+    }
+}
+
+fn rfc2822(ts: &Timestamp) -> String {
+    jiff::fmt::rfc2822::to_string(&ts.to_zoned(TimeZone::UTC)).unwrap_or_default()
+}
+
+#[derive(Template)]
+#[template(path = "atom.xml")]
+struct AtomFeed<'a> {
+    feed: &'a Feed,
+}
+
+#[derive(Template)]
+#[template(path = "rss.xml")]
+struct RssFeed<'a> {
+    feed: &'a Feed,
+}
+
+#[derive(Clone, Copy)]
+pub enum FeedFormat {
+    Atom,
+    Rss,
+}
+
+impl FeedFormat {
+    fn render(&self, feed: &Feed) -> Result<Response, StatusCode> {
+        let (content_type, rendered) = match self {
+            FeedFormat::Atom => ("application/atom+xml", AtomFeed { feed }.render()),
+            FeedFormat::Rss => ("application/rss+xml", RssFeed { feed }.render()),
+        };
+        let body = rendered.map_err(|e| {
+            tracing::error!("Failed to render feed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
+    }
+
+    fn filename(&self) -> &'static str {
+        match self {
+            FeedFormat::Atom => "feed.xml",
+            FeedFormat::Rss => "rss.xml",
+        }
+    }
+}
+
+pub fn projects_feed(
+    state: &AppState,
+    format: FeedFormat,
+    query: &[(String, String)],
+) -> Result<Feed, StatusCode> {
+    let filter = TagFilter::from_query(query).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let catalog = state.project_catalog.load();
+    let entries = catalog
+        .published()
+        .filter(|p| filter.matches(p))
+        .map(|p| FeedEntry {
+            title: p.metadata.title.clone(),
+            url: format!("{}/projects/{}", state.base_url, p.metadata.slug),
+            updated: p.metadata.start.to_timestamp(),
+            content_html: Some(p.body_html.clone()),
+            enclosure: None,
+        })
+        .collect();
+    let (title, query_string) = if filter.is_empty() {
+        ("projects | nj-vs-vh".to_owned(), String::new())
+    } else {
+        (
+            format!("projects tagged {} | nj-vs-vh", filter),
+            format!("?{}", filter.query()),
+        )
+    };
+    Ok(Feed {
+        title,
+        url: format!(
+            "{}/projects/{}{}",
+            state.base_url,
+            format.filename(),
+            query_string
+        ),
+        page_url: format!("{}/projects{}", state.base_url, query_string),
+        entries,
+    })
+}
+
+pub fn gallery_feed(state: &AppState, format: FeedFormat) -> Feed {
+    let gallery = state.gallery.load();
+    let entries = gallery
+        .images
+        .iter()
+        .take(GALLERY_FEED_SIZE)
+        .map(|img| {
+            let filename = imaging::escape_url_path(&img.filename);
+            FeedEntry {
+                title: img.title.clone().unwrap_or(img.filename.clone()),
+                url: format!("{}/gallery/{}", state.base_url, filename),
+                updated: img
+                    .timestamp
+                    .to_zoned(TimeZone::UTC)
+                    .map(|z| z.timestamp())
+                    .unwrap_or_default(),
+                content_html: Some(format!(
+                    "<img src=\"{}/gallery/media/{}\" />",
+                    state.base_url, filename
+                )),
+                enclosure: Some(Enclosure {
+                    url: format!("{}/gallery/thumbnails/{}", state.base_url, filename),
+                    mime_type: img.mime_type(),
+                    length: img.thumbnail_bytes,
+                }),
+            }
+        })
+        .collect();
+    Feed {
+        title: "gallery | nj-vs-vh".to_owned(),
+        url: format!("{}/gallery/{}", state.base_url, format.filename()),
+        page_url: format!("{}/gallery", state.base_url),
+        entries,
+    }
+}
+
+pub async fn projects_atom(
+    State(state): State<AppState>,
+    Query(query): Query<Vec<(String, String)>>,
+) -> Result<Response, StatusCode> {
+    FeedFormat::Atom.render(&projects_feed(&state, FeedFormat::Atom, &query)?)
+}
+
+pub async fn projects_rss(
+    State(state): State<AppState>,
+    Query(query): Query<Vec<(String, String)>>,
+) -> Result<Response, StatusCode> {
+    FeedFormat::Rss.render(&projects_feed(&state, FeedFormat::Rss, &query)?)
+}
+
+pub async fn gallery_atom(State(state): State<AppState>) -> Result<Response, StatusCode> {
+    FeedFormat::Atom.render(&gallery_feed(&state, FeedFormat::Atom))
+}
+
+pub async fn gallery_rss(State(state): State<AppState>) -> Result<Response, StatusCode> {
+    FeedFormat::Rss.render(&gallery_feed(&state, FeedFormat::Rss))
+}
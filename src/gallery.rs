@@ -1,79 +1,186 @@
-use exif;
+use arc_swap::ArcSwap;
 use image::{GenericImageView, Pixel};
 use jiff::civil::DateTime;
 use std::{
-    cmp::Reverse,
+    cmp,
+    collections::{BTreeMap, HashSet},
     fmt::Display,
-    fs::File,
-    io::{self, Read, Write},
+    fs, io,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
+use serde::Serialize;
+
 use crate::colorpalette::{extract_palette, PaletteExtractionAlgorithm};
+use crate::galleryalbums::{self, Album, AlbumOrder, ALBUMS_FILENAME};
+use crate::gallerycache::{self, CacheEntry, CacheManifest};
+use crate::galleryexif::{ExifDetails, GalleryFilter};
+use crate::health::LoadFailure;
+use crate::imaging::{self, ImagingError, VariantFormat};
+use crate::ContentDirs;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GalleryError {
+    #[error("Gallery dir {path:?} must be a directory")]
+    NotADirectory { path: PathBuf },
+    #[error("Image filename {path:?} contains non-unicode characters")]
+    NonUnicodeFilename { path: PathBuf },
+    #[error("Failed to read {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error(transparent)]
+    Imaging(#[from] ImagingError),
+    #[error("Failed to parse EXIF metadata from the image {path:?}: {source}")]
+    Exif { path: PathBuf, source: exif::Error },
+    #[error("EXIF metadata for {path:?} misses {field} field")]
+    MissingExifField { path: PathBuf, field: exif::Tag },
+    #[error("Failed to parse {field} from the image {path:?}: {source}")]
+    InvalidExifField {
+        path: PathBuf,
+        field: exif::Tag,
+        source: jiff::Error,
+    },
+    #[error("Failed to parse gallery albums from {path:?}: {source}")]
+    Albums {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    #[error("Album slug {slug:?} must consist of latin letters, digits, dashes and underscores")]
+    InvalidAlbumSlug { slug: String },
+    #[error("Gallery albums contain duplicate slug {slug:?}")]
+    DuplicateAlbumSlug { slug: String },
+}
 
-#[derive(Clone, Debug)]
+impl GalleryError {
+    fn io(path: &Path) -> impl FnOnce(io::Error) -> GalleryError + '_ {
+        |source| GalleryError::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct GalleryImage {
     pub filename: String,
     pub title: Option<String>,
     pub timestamp: DateTime,
     pub colorpalette: Vec<String>,
+    pub thumbnail_bytes: u64,
+    /// standard image size
+    pub width: u32,
+    pub height: u32,
+    pub exif: ExifDetails,
+    /// widths of the responsive variants, each available in all of the `VARIANT_FORMATS`
+    pub variant_widths: Vec<u32>,
+}
+
+/// settings the derivatives are produced with; changing any of them invalidates the cache
+#[derive(Debug, Serialize)]
+pub struct ProcessingParams {
+    pub max_display_width: u32,
+    pub max_display_height: u32,
+    pub thumb_width: u32,
+    pub palette_algorithm: PaletteExtractionAlgorithm,
+    pub palette_depth: usize,
+    pub palette_luma_min: u8,
+    /// widths of the responsive variants, capped by the standard image size
+    pub variant_widths: Vec<u32>,
+    pub variant_quality: u8,
+}
+
+impl Default for ProcessingParams {
+    fn default() -> Self {
+        ProcessingParams {
+            max_display_width: 2000,
+            max_display_height: 1000,
+            thumb_width: 300,
+            palette_algorithm: PaletteExtractionAlgorithm::PaletteExtractLib,
+            palette_depth: 3,
+            palette_luma_min: 60,
+            variant_widths: vec![480, 960, 1440, 2000],
+            variant_quality: 80,
+        }
+    }
+}
+
+impl ProcessingParams {
+    pub fn hash(&self) -> String {
+        let params = serde_json::to_vec(self).expect("processing params are serializable");
+        blake3::hash(&params).to_hex().to_string()
+    }
 }
 
 impl GalleryImage {
+    /// loads the image, reusing cached derivatives if they match the current original and
+    /// processing params, and records the derivatives in the cache manifest
     pub fn load(
-        filepath: &PathBuf,
-        stdmedia_dir: &Path,
-        thumbnails_dir: &Path,
+        filepath: &Path,
+        dirs: &ContentDirs,
+        params: &ProcessingParams,
+        cache: &Mutex<CacheManifest>,
         ignore_cache: bool,
-    ) -> io::Result<GalleryImage> {
+    ) -> Result<GalleryImage, GalleryError> {
         let filename = filepath
             .file_name()
-            .ok_or(io::Error::new(
-                io::ErrorKind::Other,
-                "Failed to get filename from image path",
-            ))?
-            .to_str()
-            .ok_or(io::Error::new(
-                io::ErrorKind::Other,
-                "Filename contains non-unicode characters",
-            ))?
+            .and_then(|name| name.to_str())
+            .ok_or(GalleryError::NonUnicodeFilename {
+                path: filepath.to_owned(),
+            })?
             .to_owned();
 
+        let source_hash =
+            gallerycache::source_hash(filepath).map_err(GalleryError::io(filepath))?;
+        let params_hash = params.hash();
+
         // reading image contents and generating thumbnail
-        let standard_media_path = stdmedia_dir.join(&filename);
-        let thumb_path = thumbnails_dir.join(&filename);
-        let colorpalette_path = filepath.with_file_name(format!(".{}.colors", &filename));
-        if ignore_cache
-            || !standard_media_path.exists()
-            || !thumb_path.exists()
-            || !colorpalette_path.exists()
-        {
+        let standard_media_path = dirs.gallery_stdmedia_dir.join(&filename);
+        let thumb_path = dirs.gallery_thumbnails_dir.join(&filename);
+        let variants_dir = dirs.gallery_variants_dir.join(&filename);
+        let cached = cache
+            .lock()
+            .unwrap()
+            .get(&filename, &source_hash, &params_hash)
+            .filter(|_| {
+                !ignore_cache
+                    && standard_media_path.exists()
+                    && thumb_path.exists()
+                    && variants_dir.is_dir()
+            })
+            .cloned();
+        let cache_entry = if let Some(entry) = cached {
+            entry
+        } else {
             tracing::info!("Loading and processing image: {:?}", filepath);
 
-            let full_img = image::open(filepath).map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Failed to read image file {:?}: {}", filepath, e),
-                )
-            })?;
+            let full_img = image::open(filepath).map_err(ImagingError::decode(filepath))?;
 
             // producing the main image to be displayed on the web
-            let max_display_width: u32 = 2000;
-            let max_display_height: u32 = 1000;
             let standard_img = full_img.resize(
-                max_display_width,
-                max_display_height,
+                params.max_display_width,
+                params.max_display_height,
                 image::imageops::FilterType::Lanczos3,
             );
-            if let Err(e) = standard_img.save(standard_media_path) {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "Failed to save standard-size image from {:?}: {}",
-                        filepath, e
-                    ),
-                ));
-            };
+            standard_img
+                .save(&standard_media_path)
+                .map_err(ImagingError::encode(&standard_media_path))?;
+
+            // smaller versions and more efficient formats of it for the responsive markup
+            let variant_widths =
+                imaging::variant_widths(&params.variant_widths, standard_img.width());
+            if variants_dir.exists() {
+                fs::remove_dir_all(&variants_dir).map_err(GalleryError::io(&variants_dir))?;
+            }
+            imaging::save_variants(
+                &standard_img,
+                &variant_widths,
+                params.variant_quality,
+                &variants_dir,
+            )?;
 
             // thumbnail aspect ratio is always 4:3 for gallery layout, so we crop image first
             let cropped_height = full_img.width() * 3 / 4;
@@ -94,155 +201,540 @@ impl GalleryImage {
                 )
             };
 
-            let thumb_width: u32 = 300;
+            let thumb_width = params.thumb_width;
             let thumb_height = 3 * thumb_width / 4;
             let thumb_img = cropped_img.thumbnail(thumb_width, thumb_height);
 
-            if let Err(e) = thumb_img.save(thumb_path) {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Failed to save tumbnail from {:?}: {}", filepath, e),
-                ));
+            thumb_img
+                .save(&thumb_path)
+                .map_err(ImagingError::encode(&thumb_path))?;
+            for format in imaging::MODERN_FORMATS {
+                format.save(
+                    &thumb_img,
+                    params.variant_quality,
+                    &variants_dir.join(format!("thumb.{}", format.extension())),
+                )?;
             }
 
-            let luma_min: u8 = 60;
             let mut pixels: Vec<[u8; 3]> = thumb_img
                 .pixels()
-                .filter(|(_, _, rgb)| rgb.to_luma().0[0] > luma_min)
+                .filter(|(_, _, rgb)| rgb.to_luma().0[0] > params.palette_luma_min)
                 .map(|(_, _, rgb)| [rgb.0[0], rgb.0[1], rgb.0[2]])
                 .collect();
             let colorpalette = extract_palette(
                 pixels.as_mut_slice(),
-                3,
-                &PaletteExtractionAlgorithm::PaletteExtractLib,
+                params.palette_depth,
+                &params.palette_algorithm,
             )
-            .unwrap();
+            .unwrap_or_default();
             let colorpalette_codes: Vec<String> = colorpalette
                 .iter()
                 .map(|rgb| rgb.map(|value| format!("{:02x}", value)).join(""))
                 .collect();
             tracing::info!("Extracted color palette: {}", colorpalette_codes.join(" "));
-            write!(
-                File::create(&colorpalette_path)?,
-                "{}",
-                colorpalette_codes.join("\n")
-            )?;
+            CacheEntry {
+                source_hash,
+                params_hash,
+                colorpalette: colorpalette_codes,
+                variant_widths,
+            }
         };
+        let colorpalette = cache_entry.colorpalette.clone();
+        let variant_widths = cache_entry.variant_widths.clone();
 
-        let mut contents = String::new();
-        File::open(&colorpalette_path)?.read_to_string(&mut contents)?;
-        let colorpalette: Vec<String> = contents
-            .lines()
-            .filter(|s| s.len() == 6)
-            .map(|s| s.to_owned())
-            .collect();
+        let thumbnail_bytes = std::fs::metadata(&thumb_path)
+            .map_err(GalleryError::io(&thumb_path))?
+            .len();
 
-        // reading image metadata from EXIF
-        let rawfile = std::fs::File::open(filepath)?;
-        let mut bufreader = std::io::BufReader::new(&rawfile);
-        let exifreader = exif::Reader::new();
-        let exif_data = exifreader
-            .read_from_container(&mut bufreader)
-            .map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "Failed to parse EXIF metadata from the image {:?}: {}",
-                        filepath, e
-                    ),
-                )
-            })?;
+        let (width, height) = image::image_dimensions(&standard_media_path)
+            .map_err(ImagingError::decode(&standard_media_path))?;
 
-        Ok(GalleryImage {
+        let (title, timestamp, mut exif) = read_exif(filepath)?;
+        let (full_width, full_height) =
+            image::image_dimensions(filepath).map_err(ImagingError::decode(filepath))?;
+        exif.width = Some(full_width);
+        exif.height = Some(full_height);
+        let image = GalleryImage {
             filename,
-            title: exif_data
-                .get_field(exif::Tag::ImageDescription, exif::In::PRIMARY)
-                .map(|f| f.display_value().to_string().trim_matches('"').to_string()),
-            timestamp: exif_data
-                .get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)
-                .ok_or(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "EXIF metadata for {:?} misses DateTimeOriginal field ",
-                        filepath
-                    ),
-                ))?
-                .display_value()
-                .to_string()
-                .parse()
-                .map_err(|e| {
-                    io::Error::new(
-                        io::ErrorKind::Other,
-                        format!(
-                            "Failed to parse DateTimeOriginal from the image {:?}: {}",
-                            filepath, e
-                        ),
-                    )
-                })?,
+            title,
+            timestamp,
             colorpalette,
-        })
+            thumbnail_bytes,
+            width,
+            height,
+            exif,
+            variant_widths,
+        };
+        cache
+            .lock()
+            .unwrap()
+            .insert(image.filename.clone(), cache_entry);
+        Ok(image)
     }
 
     pub fn month_year(&self) -> String {
         self.timestamp.date().strftime("%B %Y").to_string()
     }
+
+    pub fn mime_type(&self) -> &'static str {
+        image::ImageFormat::from_path(&self.filename)
+            .map(|f| f.to_mime_type())
+            .unwrap_or("application/octet-stream")
+    }
+
+    fn variants_url(&self) -> String {
+        format!(
+            "/gallery/variants/{}",
+            imaging::escape_url_path(&self.filename)
+        )
+    }
+
+    pub fn srcset(&self, format: &VariantFormat) -> String {
+        imaging::srcset(&self.variants_url(), &self.variant_widths, *format)
+    }
+
+    /// JPEG variants for the `<img>` itself
+    pub fn fallback_srcset(&self) -> String {
+        self.srcset(&VariantFormat::Jpeg)
+    }
+
+    pub fn thumbnail_url(&self, format: &VariantFormat) -> String {
+        format!("{}/thumb.{}", self.variants_url(), format.extension())
+    }
+
+    /// formats for `<source>` elements, in order of preference
+    pub fn source_formats(&self) -> &'static [VariantFormat] {
+        &imaging::MODERN_FORMATS
+    }
+}
+
+/// reads the title, the time the photo was taken and the details on how it was taken from the
+/// image's EXIF metadata
+pub fn read_exif(filepath: &Path) -> Result<(Option<String>, DateTime, ExifDetails), GalleryError> {
+    let rawfile = std::fs::File::open(filepath).map_err(GalleryError::io(filepath))?;
+    let mut bufreader = std::io::BufReader::new(&rawfile);
+    let exifreader = exif::Reader::new();
+    let exif_data = exifreader
+        .read_from_container(&mut bufreader)
+        .map_err(|source| GalleryError::Exif {
+            path: filepath.to_owned(),
+            source,
+        })?;
+    let title = exif_data
+        .get_field(exif::Tag::ImageDescription, exif::In::PRIMARY)
+        .map(|f| f.display_value().to_string().trim_matches('"').to_string());
+    let timestamp = exif_data
+        .get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)
+        .ok_or(GalleryError::MissingExifField {
+            path: filepath.to_owned(),
+            field: exif::Tag::DateTimeOriginal,
+        })?
+        .display_value()
+        .to_string()
+        .parse()
+        .map_err(|source| GalleryError::InvalidExifField {
+            path: filepath.to_owned(),
+            field: exif::Tag::DateTimeOriginal,
+            source,
+        })?;
+    Ok((title, timestamp, ExifDetails::read(&exif_data)))
+}
+
+/// gallery images are all non-hidden files in the gallery dir, except for the albums file;
+/// hidden ones are reserved for the cache manifest
+pub fn is_gallery_file(path: &Path) -> bool {
+    path.is_file()
+        && path.file_name().is_some_and(|name| {
+            let name = name.to_string_lossy();
+            !name.starts_with('.') && name != ALBUMS_FILENAME
+        })
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct Gallery {
     pub images: Vec<GalleryImage>,
+    /// images that failed to load
+    pub failed: Vec<LoadFailure>,
+    /// number of images still being processed in background
+    pub pending: usize,
+    /// former filenames of renamed images, mapped to the current ones
+    pub renamed: BTreeMap<String, String>,
+    pub albums: Vec<Album>,
 }
 
 impl Display for Gallery {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&format!("Gallery {{ {} images }}", self.images.len(),))
+        f.write_str(&format!(
+            "Gallery {{ {} images, {} failed, {} pending }}",
+            self.images.len(),
+            self.failed.len(),
+            self.pending
+        ))
     }
 }
 
-impl Gallery {
-    pub fn load(
-        src_dir: &Path,
-        stdmedia_dir: &Path,
-        thumbnails_dir: &Path,
-        ignore_cache: bool,
-    ) -> io::Result<Gallery> {
-        tracing::info!("Loading gallery from {:?}", src_dir);
-        if !src_dir.is_dir() {
-            return Err(io::Error::other(
-                "gallery dir must be a directory".to_owned(),
-            ));
+/// lists original images in the gallery dir
+pub fn sources(src_dir: &Path) -> Result<Vec<PathBuf>, GalleryError> {
+    if !src_dir.is_dir() {
+        return Err(GalleryError::NotADirectory {
+            path: src_dir.to_owned(),
+        });
+    }
+    Ok(src_dir
+        .read_dir()
+        .map_err(GalleryError::io(src_dir))?
+        .filter_map(|maybe_dir_entry| match maybe_dir_entry {
+            Ok(entry) => Some(entry.path()),
+            Err(e) => {
+                tracing::warn!("Not a valid dir entry: {}", e);
+                None
+            }
+        })
+        .filter(|path| is_gallery_file(path))
+        .collect())
+}
+
+/// images currently being processed; the initial load and the watcher may get to the same image
+/// at once, and must neither write its derivatives concurrently nor publish an older result over
+/// a newer one, so each of them holds the image's guard from processing to publishing
+#[derive(Default)]
+pub struct ImageLocks {
+    busy: Mutex<HashSet<String>>,
+    released: Condvar,
+}
+
+impl ImageLocks {
+    /// waits for whoever is processing the image to publish it
+    pub fn lock(&self, filename: &str) -> ImageGuard<'_> {
+        let mut busy = self.busy.lock().unwrap();
+        while busy.contains(filename) {
+            busy = self.released.wait(busy).unwrap();
         }
-        let mut images: Vec<GalleryImage> = src_dir
-            .read_dir()?
-            .filter_map(|maybe_dir_entry| match maybe_dir_entry {
-                Ok(entry) => {
-                    if entry.file_name().to_string_lossy().chars().next() == Some('.') {
-                        return None;
-                    }
+        busy.insert(filename.to_owned());
+        ImageGuard {
+            locks: self,
+            filename: filename.to_owned(),
+        }
+    }
+}
+
+pub struct ImageGuard<'a> {
+    locks: &'a ImageLocks,
+    filename: String,
+}
+
+impl Drop for ImageGuard<'_> {
+    fn drop(&mut self) {
+        self.locks.busy.lock().unwrap().remove(&self.filename);
+        self.locks.released.notify_all();
+    }
+}
+
+/// every update of the gallery copies it, so loaded images are published in batches
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// how the initial load goes, as opposed to what it produces
+pub struct LoadOptions {
+    /// reprocess everything, e.g. after changing the processing code
+    pub ignore_cache: bool,
+    /// number of images processed in parallel
+    pub workers: usize,
+}
+
+/// processes gallery images on `options.workers` threads, publishing them into `gallery` shortly after
+/// they are ready, so that the site can be served while processing is still going on;
+/// previously processed images go first, since they are likely to be served from cache quickly
+pub fn load(
+    mut sources: Vec<PathBuf>,
+    dirs: &ContentDirs,
+    params: &ProcessingParams,
+    cache: &Mutex<CacheManifest>,
+    locks: &ImageLocks,
+    options: &LoadOptions,
+    gallery: &ArcSwap<Gallery>,
+) {
+    let LoadOptions {
+        ignore_cache,
+        workers,
+    } = *options;
+    let src_dir = &dirs.gallery_dir;
+    tracing::info!(
+        "Loading gallery from {:?}: {} images, {} workers",
+        src_dir,
+        sources.len(),
+        workers
+    );
+    {
+        let cache = cache.lock().unwrap();
+        sources.sort_by_key(|path| {
+            !path
+                .file_name()
+                .is_some_and(|name| cache.contains(&name.to_string_lossy()))
+        });
+    }
+
+    // albums only refer to the images, so they are usable before the images are loaded
+    reload_albums(src_dir, gallery);
 
-                    match GalleryImage::load(
-                        &entry.path(),
-                        stdmedia_dir,
-                        thumbnails_dir,
-                        ignore_cache,
-                    ) {
-                        Ok(image) => Some(image),
-                        Err(e) => {
-                            tracing::warn!("Failed to load gallery image from {:?}: {}", entry, e);
-                            None
+    let started = Instant::now();
+    let total = sources.len();
+    let renamed = cache.lock().unwrap().renamed().clone();
+    gallery.rcu(|g| Gallery {
+        pending: total,
+        renamed: renamed.clone(),
+        ..Gallery::clone(g)
+    });
+    let next = AtomicUsize::new(0);
+    let processed = AtomicUsize::new(0);
+    // images are kept locked until published, so that the watcher can't reload them meanwhile
+    type Loaded<'a> = (ImageGuard<'a>, Result<GalleryImage, LoadFailure>);
+    let batch: Mutex<(Vec<Loaded>, Instant)> = Mutex::new((Vec::new(), Instant::now()));
+    let publish = |batch: Vec<Loaded>| {
+        let (_guards, loaded): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+        gallery.rcu(|g| {
+            let mut g = g.with_loaded(&loaded);
+            g.pending = g.pending.saturating_sub(loaded.len());
+            g
+        });
+    };
+    std::thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            scope.spawn(|| {
+                while let Some(path) = sources.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let guard = locks.lock(&path.file_name().unwrap_or_default().to_string_lossy());
+                    let loaded = GalleryImage::load(path, dirs, params, cache, ignore_cache)
+                        .map_err(|e| {
+                            tracing::warn!("Failed to load gallery image from {:?}: {}", path, e);
+                            LoadFailure {
+                                path: path.clone(),
+                                error: e.to_string(),
+                            }
+                        });
+                    {
+                        let mut batch = batch.lock().unwrap();
+                        batch.0.push((guard, loaded));
+                        if batch.1.elapsed() >= PUBLISH_INTERVAL {
+                            publish(std::mem::take(&mut batch.0));
+                            batch.1 = Instant::now();
                         }
                     }
+                    let done = processed.fetch_add(1, Ordering::Relaxed) + 1;
+                    let eta = started
+                        .elapsed()
+                        .mul_f64((total - done) as f64 / done as f64);
+                    tracing::info!(
+                        "Processed gallery images: {}/{}, ETA {}s",
+                        done,
+                        total,
+                        eta.as_secs()
+                    );
                 }
-                Err(e) => {
-                    tracing::warn!("Not a valid dir entry: {}", e);
-                    None
-                }
+            });
+        }
+    });
+
+    publish(std::mem::take(&mut batch.lock().unwrap().0));
+
+    // images may have been added or removed by the watcher in the meantime, so the startup
+    // listing is outdated; anything in the gallery dir or still served is kept
+    let mut cache = cache.lock().unwrap();
+    let mut filenames: HashSet<String> = self::sources(src_dir)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|path| path.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect();
+    filenames.extend(gallery.load().images.iter().map(|img| img.filename.clone()));
+    cache.gc(&filenames, src_dir, &derivative_dirs(dirs));
+    gallery.rcu(|g| g.with_renamed(cache.renamed()));
+    if let Err(e) = cache.save(src_dir) {
+        tracing::warn!("Failed to save gallery cache manifest: {}", e);
+    }
+    tracing::info!(
+        "Loaded gallery in {:.1}s: {}",
+        started.elapsed().as_secs_f32(),
+        gallery.load()
+    );
+}
+
+/// (re)processes a single image from `filepath` and updates it in the gallery; if the file was
+/// removed or fails to load, the image is dropped from the gallery
+pub fn reload_image(
+    filepath: &Path,
+    dirs: &ContentDirs,
+    params: &ProcessingParams,
+    cache: &Mutex<CacheManifest>,
+    locks: &ImageLocks,
+    gallery: &ArcSwap<Gallery>,
+) {
+    let Some(filename) = filepath.file_name().map(|f| f.to_string_lossy()) else {
+        return;
+    };
+    let _guard = locks.lock(&filename);
+    if is_gallery_file(filepath) {
+        match GalleryImage::load(filepath, dirs, params, cache, false) {
+            Ok(image) => gallery.rcu(|g| g.with_image(image.clone())),
+            Err(e) => {
+                tracing::warn!("Failed to reload gallery image from {:?}: {}", filepath, e);
+                let failure = LoadFailure {
+                    path: filepath.to_owned(),
+                    error: e.to_string(),
+                };
+                gallery.rcu(|g| g.with_failure(failure.clone()))
+            }
+        };
+    } else {
+        cache
+            .lock()
+            .unwrap()
+            .remove(&filename, &derivative_dirs(dirs));
+        gallery.rcu(|g| g.without_image(&filename));
+    }
+    let cache = cache.lock().unwrap();
+    gallery.rcu(|g| g.with_renamed(cache.renamed()));
+    if let Err(e) = cache.save(&dirs.gallery_dir) {
+        tracing::warn!("Failed to save gallery cache manifest: {}", e);
+    }
+}
+
+/// (re)reads the albums file and updates the albums in the gallery; if it fails to load, the
+/// gallery is left without albums
+pub fn reload_albums(gallery_dir: &Path, gallery: &ArcSwap<Gallery>) {
+    match galleryalbums::load(gallery_dir) {
+        Ok(albums) => {
+            tracing::info!("Loaded {} gallery albums", albums.len());
+            gallery.rcu(|g| g.with_albums(albums.clone()))
+        }
+        Err(e) => {
+            tracing::warn!("Failed to load gallery albums: {}", e);
+            let failure = LoadFailure {
+                path: gallery_dir.join(ALBUMS_FILENAME),
+                error: e.to_string(),
+            };
+            gallery.rcu(|g| g.with_albums(Vec::new()).with_failure(failure.clone()))
+        }
+    };
+}
+
+/// dirs with files (or subdirs) named after the original images they were produced from
+fn derivative_dirs(dirs: &ContentDirs) -> [&Path; 3] {
+    [
+        &dirs.gallery_stdmedia_dir,
+        &dirs.gallery_thumbnails_dir,
+        &dirs.gallery_variants_dir,
+    ]
+}
+
+impl Gallery {
+    /// returns a new gallery with the image added, replacing the one with the same filename
+    pub fn with_image(&self, image: GalleryImage) -> Gallery {
+        let mut gallery = self.without_image(&image.filename);
+        let index = gallery
+            .images
+            .partition_point(|img| img.timestamp >= image.timestamp);
+        gallery.images.insert(index, image);
+        gallery
+    }
+
+    /// returns a new gallery without the image and its load failure, if any
+    pub fn without_image(&self, filename: &str) -> Gallery {
+        Gallery {
+            images: self
+                .images
+                .iter()
+                .filter(|img| img.filename != filename)
+                .cloned()
+                .collect(),
+            failed: self
+                .failed
+                .iter()
+                .filter(|f| f.path.file_name().is_none_or(|name| name != filename))
+                .cloned()
+                .collect(),
+            pending: self.pending,
+            renamed: self.renamed.clone(),
+            albums: self.albums.clone(),
+        }
+    }
+
+    /// like `with_image` and `with_failure` for many images at once, copying the gallery once
+    pub fn with_loaded(&self, loaded: &[Result<GalleryImage, LoadFailure>]) -> Gallery {
+        let filenames: HashSet<String> = loaded
+            .iter()
+            .filter_map(|result| match result {
+                Ok(image) => Some(image.filename.clone()),
+                Err(failure) => failure
+                    .path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned()),
             })
             .collect();
+        let mut gallery = Gallery {
+            images: self
+                .images
+                .iter()
+                .filter(|img| !filenames.contains(&img.filename))
+                .cloned()
+                .collect(),
+            failed: self
+                .failed
+                .iter()
+                .filter(|f| {
+                    f.path
+                        .file_name()
+                        .is_none_or(|name| !filenames.contains(name.to_string_lossy().as_ref()))
+                })
+                .cloned()
+                .collect(),
+            pending: self.pending,
+            renamed: self.renamed.clone(),
+            albums: self.albums.clone(),
+        };
+        for result in loaded {
+            match result {
+                Ok(image) => gallery.images.push(image.clone()),
+                Err(failure) => gallery.failed.push(failure.clone()),
+            }
+        }
+        // stable, so images taken at the same time keep their order
+        gallery
+            .images
+            .sort_by_key(|img| cmp::Reverse(img.timestamp));
+        gallery
+    }
 
-        images.sort_by_key(|img| Reverse(img.timestamp));
+    /// returns a new gallery with the failure recorded instead of the image it's about
+    pub fn with_failure(&self, failure: LoadFailure) -> Gallery {
+        let filename = failure
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut gallery = self.without_image(&filename);
+        gallery.failed.push(failure);
+        gallery
+    }
 
-        Ok(Gallery { images })
+    /// returns a new gallery with the albums replaced, forgetting their previous load failure
+    pub fn with_albums(&self, albums: Vec<Album>) -> Gallery {
+        let mut gallery = self.without_image(ALBUMS_FILENAME);
+        gallery.albums = albums;
+        gallery
+    }
+
+    pub fn with_renamed(&self, renamed: &BTreeMap<String, String>) -> Gallery {
+        Gallery {
+            renamed: renamed.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// current filename of a renamed image, if it's in the gallery
+    pub fn find_renamed<'a>(&'a self, former: &str) -> Option<&'a str> {
+        self.renamed
+            .get(former)
+            .filter(|current| self.images.iter().any(|img| &img.filename == *current))
+            .map(|current| current.as_str())
     }
 
     pub fn find<'a>(&'a self, slug: &str) -> Option<FoundGalleryImage<'a>> {
@@ -258,21 +750,107 @@ impl Gallery {
                     None
                 },
                 next: self.images.get(index + 1),
+                album: None,
             })
     }
 
+    /// like `find`, but with the prev/next navigation going through the album
+    pub fn find_in_album<'a>(
+        &'a self,
+        slug: &str,
+        album: &'a Album,
+    ) -> Option<FoundGalleryImage<'a>> {
+        let images = self.album_images(album);
+        let index = images.iter().position(|img| img.filename == slug)?;
+        Some(FoundGalleryImage {
+            image: images[index],
+            prev: index.checked_sub(1).map(|i| images[i]),
+            next: images.get(index + 1).copied(),
+            album: Some(album),
+        })
+    }
+
+    pub fn album(&self, slug: &str) -> Option<&Album> {
+        self.albums.iter().find(|album| album.slug == slug)
+    }
+
+    fn image(&self, filename: &str) -> Option<&GalleryImage> {
+        self.images.iter().find(|img| img.filename == filename)
+    }
+
+    /// album images present in the gallery, in the album's order; renamed images are still
+    /// found under their former filenames
+    pub fn album_images<'a>(&'a self, album: &Album) -> Vec<&'a GalleryImage> {
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut images: Vec<&GalleryImage> = album
+            .images
+            .iter()
+            .filter_map(|filename| {
+                self.image(filename)
+                    .or_else(|| self.find_renamed(filename).and_then(|f| self.image(f)))
+            })
+            .filter(|img| seen.insert(&img.filename))
+            .collect();
+        match album.order {
+            AlbumOrder::Newest => images.sort_by_key(|img| cmp::Reverse(img.timestamp)),
+            AlbumOrder::Oldest => images.sort_by_key(|img| img.timestamp),
+            AlbumOrder::Manual => {}
+        }
+        images
+    }
+
+    /// image shown for the album in the album list
+    pub fn album_cover(&self, album: &Album) -> Option<&GalleryImage> {
+        album
+            .cover
+            .as_deref()
+            .and_then(|cover| self.image(cover))
+            .or_else(|| self.album_images(album).first().copied())
+    }
+
+    /// images taken with the camera and lens in the filter, newest first
+    pub fn filtered(&self, filter: &GalleryFilter) -> Vec<&GalleryImage> {
+        self.images
+            .iter()
+            .filter(|img| filter.matches(img))
+            .collect()
+    }
+
     pub fn size(&self) -> usize {
         self.images.len()
     }
 
     pub fn total_pages(&self, pagesize: usize) -> usize {
-        self.size() / pagesize + usize::from(self.size() % pagesize > 0)
+        total_pages(self.size(), pagesize)
     }
 }
 
+pub fn total_pages(size: usize, pagesize: usize) -> usize {
+    size / pagesize + usize::from(!size.is_multiple_of(pagesize))
+}
+
+/// items on the given 1-based page; empty for pages past the end
+pub fn page<T>(items: &[T], page: usize, pagesize: usize) -> &[T] {
+    let start_idx = cmp::min(pagesize * page.saturating_sub(1), items.len());
+    let end_idx = cmp::min(start_idx + pagesize, items.len());
+    &items[start_idx..end_idx]
+}
+
 #[derive(Clone)]
 pub struct FoundGalleryImage<'a> {
     pub image: &'a GalleryImage,
     pub prev: Option<&'a GalleryImage>,
     pub next: Option<&'a GalleryImage>,
+    /// album the navigation is scoped to
+    pub album: Option<&'a Album>,
+}
+
+impl FoundGalleryImage<'_> {
+    /// query string keeping the navigation within the album, if any
+    pub fn nav_query(&self) -> String {
+        match self.album {
+            Some(album) => format!("?album={}", album.slug),
+            None => String::new(),
+        }
+    }
 }
@@ -0,0 +1,72 @@
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::gallery::GalleryError;
+use crate::project::{is_valid_slug, Markdown};
+
+/// albums are defined in a single file in the gallery dir, next to the originals
+pub const ALBUMS_FILENAME: &str = "albums.yaml";
+
+/// order of the images on the album page and in its prev/next navigation
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlbumOrder {
+    /// like the gallery itself
+    #[default]
+    Newest,
+    Oldest,
+    /// as listed in the album
+    Manual,
+}
+
+/// named collection of gallery images; an image may be in any number of albums
+#[derive(Deserialize, Debug, Clone)]
+pub struct Album {
+    pub slug: String,
+    pub title: String,
+    #[serde(default)]
+    pub description: Markdown,
+    /// filename of the image representing the album, the first one by default
+    pub cover: Option<String>,
+    #[serde(default)]
+    pub order: AlbumOrder,
+    /// filenames of the images in the gallery dir
+    pub images: Vec<String>,
+}
+
+/// albums in the order they are listed in the file
+pub fn parse(text: &str, path: &Path) -> Result<Vec<Album>, GalleryError> {
+    let albums: Vec<Album> = serde_yaml::from_str(text).map_err(|source| GalleryError::Albums {
+        path: path.to_owned(),
+        source,
+    })?;
+    let mut slugs: HashSet<&str> = HashSet::new();
+    for album in albums.iter() {
+        if !is_valid_slug(&album.slug) {
+            return Err(GalleryError::InvalidAlbumSlug {
+                slug: album.slug.clone(),
+            });
+        }
+        if !slugs.insert(&album.slug) {
+            return Err(GalleryError::DuplicateAlbumSlug {
+                slug: album.slug.clone(),
+            });
+        }
+    }
+    Ok(albums)
+}
+
+/// missing albums file is not an error, the gallery just has no albums
+pub fn load(gallery_dir: &Path) -> Result<Vec<Album>, GalleryError> {
+    let path: PathBuf = gallery_dir.join(ALBUMS_FILENAME);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(source) => return Err(GalleryError::Io { path, source }),
+    };
+    parse(&text, &path)
+}
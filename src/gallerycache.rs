@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+const MANIFEST_FILENAME: &str = ".cache-manifest.json";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    #[error("Failed to serialize gallery cache manifest: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("Failed to write gallery cache manifest {path:?}: {source}")]
+    Write { path: PathBuf, source: io::Error },
+}
+
+/// derivatives produced from a single original image, along with the inputs they were
+/// produced from
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheEntry {
+    pub source_hash: String,
+    pub params_hash: String,
+    pub colorpalette: Vec<String>,
+    #[serde(default)]
+    pub variant_widths: Vec<u32>,
+}
+
+/// records which version of each original image and which processing settings the cached
+/// derivatives (standard-size image, thumbnail, palette) were produced from; stored as a hidden
+/// file in the gallery dir
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CacheManifest {
+    entries: BTreeMap<String, CacheEntry>,
+    /// former filenames of renamed originals, mapped to the current ones; renames are
+    /// recognized by the originals' content hashes
+    #[serde(default)]
+    renamed: BTreeMap<String, String>,
+    /// filenames of the originals removed while the server is running, by content hash, in
+    /// case they reappear under another name
+    #[serde(skip)]
+    removed: HashMap<String, String>,
+}
+
+impl CacheManifest {
+    /// missing or corrupted manifest is not an error, it only means everything is reprocessed
+    pub fn load(gallery_dir: &Path) -> CacheManifest {
+        let path = gallery_dir.join(MANIFEST_FILENAME);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return CacheManifest::default(),
+            Err(e) => {
+                tracing::warn!("Failed to open gallery cache manifest {:?}: {}", path, e);
+                return CacheManifest::default();
+            }
+        };
+        serde_json::from_reader(io::BufReader::new(file)).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring invalid gallery cache manifest {:?}: {}", path, e);
+            CacheManifest::default()
+        })
+    }
+
+    pub fn save(&self, gallery_dir: &Path) -> Result<(), CacheError> {
+        // writing through a temp file so that an interrupted write doesn't corrupt the manifest
+        let path = gallery_dir.join(MANIFEST_FILENAME);
+        let tmp_path = gallery_dir.join(format!("{}.tmp", MANIFEST_FILENAME));
+        fs::write(&tmp_path, serde_json::to_vec_pretty(self)?).map_err(|source| {
+            CacheError::Write {
+                path: tmp_path.clone(),
+                source,
+            }
+        })?;
+        fs::rename(&tmp_path, &path).map_err(|source| CacheError::Write { path, source })
+    }
+
+    /// cached entry for the image, if it was produced from the same original with the same
+    /// processing params
+    pub fn get(&self, filename: &str, source_hash: &str, params_hash: &str) -> Option<&CacheEntry> {
+        self.entries
+            .get(filename)
+            .filter(|e| e.source_hash == source_hash && e.params_hash == params_hash)
+    }
+
+    pub fn insert(&mut self, filename: String, entry: CacheEntry) {
+        if let Some(former) = self.removed.remove(&entry.source_hash) {
+            if former != filename {
+                self.record_rename(former, &filename);
+            }
+        }
+        // an existing original always takes precedence over a redirect
+        self.renamed.remove(&filename);
+        self.entries.insert(filename, entry);
+    }
+
+    pub fn renamed(&self) -> &BTreeMap<String, String> {
+        &self.renamed
+    }
+
+    /// whether the image has been processed before, regardless of whether it's up to date
+    pub fn contains(&self, filename: &str) -> bool {
+        self.entries.contains_key(filename)
+    }
+
+    /// drops the entry and derivatives of a single removed original
+    pub fn remove(&mut self, filename: &str, derivative_dirs: &[&Path]) {
+        if let Some(entry) = self.entries.remove(filename) {
+            self.forget(filename.to_owned(), entry);
+        }
+        for dir in derivative_dirs {
+            remove_entries(dir, |name| name == filename);
+        }
+    }
+
+    /// drops entries and derivatives of originals not in `filenames` (i.e. not present in the
+    /// gallery dir anymore), as well as leftovers from
+    /// the older caching scheme (`.<filename>.colors` files next to the originals)
+    pub fn gc(
+        &mut self,
+        filenames: &HashSet<String>,
+        gallery_dir: &Path,
+        derivative_dirs: &[&Path],
+    ) {
+        let (present, missing): (BTreeMap<_, _>, BTreeMap<_, _>) =
+            std::mem::take(&mut self.entries)
+                .into_iter()
+                .partition(|(filename, _)| filenames.contains(filename));
+        self.entries = present;
+        for (filename, entry) in missing {
+            self.forget(filename, entry);
+        }
+        for dir in derivative_dirs {
+            remove_entries(dir, |name| !filenames.contains(name));
+        }
+        remove_entries(gallery_dir, |name| {
+            name.starts_with('.') && name.ends_with(".colors")
+        });
+    }
+
+    /// takes note of a removed original, which may have been renamed to another existing one
+    fn forget(&mut self, filename: String, entry: CacheEntry) {
+        let renamed_to = self
+            .entries
+            .iter()
+            .find(|(_, e)| e.source_hash == entry.source_hash)
+            .map(|(current, _)| current.clone());
+        match renamed_to {
+            Some(current) => self.record_rename(filename, &current),
+            None => {
+                self.removed.insert(entry.source_hash, filename);
+            }
+        }
+    }
+
+    fn record_rename(&mut self, from: String, to: &str) {
+        tracing::info!("Gallery image {:?} was renamed to {:?}", from, to);
+        // redirecting straight to the current name instead of chaining
+        for current in self.renamed.values_mut() {
+            if *current == from {
+                *current = to.to_owned();
+            }
+        }
+        self.renamed.insert(from, to.to_owned());
+        self.renamed.remove(to);
+    }
+}
+
+/// content hash of the original image
+pub fn source_hash(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// removes files and dirs in `dir` with names matching the predicate
+fn remove_entries(dir: &Path, predicate: impl Fn(&str) -> bool) {
+    let Ok(entries) = dir.read_dir() else {
+        return;
+    };
+    let stale: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| predicate(&entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path())
+        .collect();
+    for path in stale {
+        tracing::info!("Removing stale gallery cache entry {:?}", path);
+        let result = if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to remove {:?}: {}", path, e);
+        }
+    }
+}
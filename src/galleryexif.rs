@@ -0,0 +1,266 @@
+use exif::{Exif, In, Tag, Value};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::gallery::GalleryImage;
+
+/// how the photo was taken, as far as its EXIF metadata tells; any field may be missing
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ExifDetails {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens: Option<String>,
+    /// in mm
+    pub focal_length: Option<f64>,
+    pub focal_length_35mm: Option<u32>,
+    /// f-number
+    pub aperture: Option<f64>,
+    /// in seconds
+    pub exposure_time: Option<f64>,
+    pub iso: Option<u32>,
+    /// size of the original, which is usually larger than the one served; taken from the image
+    /// itself, since editors don't always keep the one in EXIF up to date
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl ExifDetails {
+    pub fn read(exif: &Exif) -> ExifDetails {
+        ExifDetails {
+            make: read_string(exif, Tag::Make),
+            model: read_string(exif, Tag::Model),
+            lens: read_string(exif, Tag::LensModel),
+            focal_length: read_rational(exif, Tag::FocalLength),
+            focal_length_35mm: read_uint(exif, Tag::FocalLengthIn35mmFilm).filter(|&f| f > 0),
+            aperture: read_rational(exif, Tag::FNumber),
+            exposure_time: read_rational(exif, Tag::ExposureTime),
+            iso: read_uint(exif, Tag::PhotographicSensitivity),
+            width: None,
+            height: None,
+        }
+    }
+
+    /// make and model, without repeating the make if the model already starts with it
+    /// (as in "Canon" + "Canon PowerShot G11")
+    pub fn camera(&self) -> Option<String> {
+        match (&self.make, &self.model) {
+            (Some(make), Some(model)) => {
+                let brand = make.split_whitespace().next().unwrap_or(make);
+                if model.to_lowercase().starts_with(&brand.to_lowercase()) {
+                    Some(model.clone())
+                } else {
+                    Some(format!("{} {}", make, model))
+                }
+            }
+            (make, model) => make.clone().or(model.clone()),
+        }
+    }
+
+    /// exposure settings, e.g. "6.1 mm (28 mm eq.)", "f/2.8", "1/250 s", "ISO 100"
+    pub fn settings(&self) -> Vec<String> {
+        let focal_length = match (self.focal_length, self.focal_length_35mm) {
+            (Some(f), Some(f35)) if f.round() as u32 != f35 => {
+                Some(format!("{} mm ({} mm eq.)", round1(f), f35))
+            }
+            (Some(f), _) => Some(format!("{} mm", round1(f))),
+            (None, f35) => f35.map(|f35| format!("{} mm eq.", f35)),
+        };
+        let exposure_time = self.exposure_time.map(|t| {
+            if t < 1.0 {
+                format!("1/{} s", (1.0 / t).round())
+            } else {
+                format!("{} s", round1(t))
+            }
+        });
+        [
+            focal_length,
+            self.aperture.map(|a| format!("f/{}", round1(a))),
+            exposure_time,
+            self.iso.map(|iso| format!("ISO {}", iso)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    pub fn dimensions(&self) -> Option<String> {
+        Some(format!("{} × {}", self.width?, self.height?))
+    }
+
+    pub fn camera_url(&self) -> Option<String> {
+        self.camera()
+            .map(|camera| GalleryFilter::by_camera(&camera).url())
+    }
+
+    pub fn lens_url(&self) -> Option<String> {
+        self.lens
+            .as_ref()
+            .map(|lens| GalleryFilter::by_lens(lens).url())
+    }
+}
+
+fn round1(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+fn read_string(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|bytes| {
+                String::from_utf8_lossy(bytes)
+                    .trim_matches(|c: char| c.is_whitespace() || c == '\0')
+                    .to_owned()
+            })
+            .filter(|s| !s.is_empty()),
+        _ => None,
+    }
+}
+
+fn read_rational(exif: &Exif, tag: Tag) -> Option<f64> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) => values
+            .first()
+            .map(|r| r.to_f64())
+            .filter(|v| v.is_finite() && *v > 0.0),
+        _ => None,
+    }
+}
+
+fn read_uint(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+/// gallery images taken with the given camera and/or lens
+#[derive(Debug, Default, Clone)]
+pub struct GalleryFilter {
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+}
+
+impl GalleryFilter {
+    pub fn from_query(params: &HashMap<String, String>) -> GalleryFilter {
+        let param = |key: &str| params.get(key).filter(|v| !v.is_empty()).cloned();
+        GalleryFilter {
+            camera: param("camera"),
+            lens: param("lens"),
+        }
+    }
+
+    pub fn by_camera(camera: &str) -> GalleryFilter {
+        GalleryFilter {
+            camera: Some(camera.to_owned()),
+            lens: None,
+        }
+    }
+
+    pub fn by_lens(lens: &str) -> GalleryFilter {
+        GalleryFilter {
+            camera: None,
+            lens: Some(lens.to_owned()),
+        }
+    }
+
+    pub fn matches(&self, image: &GalleryImage) -> bool {
+        self.camera
+            .as_ref()
+            .is_none_or(|camera| image.exif.camera().as_ref() == Some(camera))
+            && self
+                .lens
+                .as_ref()
+                .is_none_or(|lens| image.exif.lens.as_ref() == Some(lens))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.camera.is_none() && self.lens.is_none()
+    }
+
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        [("camera", &self.camera), ("lens", &self.lens)]
+            .into_iter()
+            .filter_map(|(key, value)| value.clone().map(|v| (key, v)))
+            .collect()
+    }
+
+    /// URL query string representing the filter, without the leading `?`
+    pub fn query(&self) -> String {
+        form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(self.params())
+            .finish()
+    }
+
+    /// query string to prepend other parameters (e.g. page number) to, empty or ending with `&`
+    pub fn query_prefix(&self) -> String {
+        if self.is_empty() {
+            String::new()
+        } else {
+            format!("{}&", self.query())
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("/gallery?{}", self.query())
+    }
+}
+
+impl std::fmt::Display for GalleryFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.camera, &self.lens) {
+            (Some(camera), Some(lens)) => write!(f, "{} with {}", camera, lens),
+            (Some(camera), None) => f.write_str(camera),
+            (None, Some(lens)) => f.write_str(lens),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// camera or lens along with the number of images taken with it
+pub struct EquipmentCount {
+    pub name: String,
+    pub count: usize,
+    /// gallery filtered by it
+    pub url: String,
+}
+
+/// cameras and lenses the gallery images were taken with, in alphabetical order
+pub struct Equipment {
+    pub cameras: Vec<EquipmentCount>,
+    pub lenses: Vec<EquipmentCount>,
+}
+
+impl Equipment {
+    pub fn count<'a>(images: impl IntoIterator<Item = &'a GalleryImage>) -> Equipment {
+        let mut cameras: BTreeMap<String, usize> = BTreeMap::new();
+        let mut lenses: BTreeMap<String, usize> = BTreeMap::new();
+        for image in images {
+            if let Some(camera) = image.exif.camera() {
+                *cameras.entry(camera).or_default() += 1;
+            }
+            if let Some(lens) = &image.exif.lens {
+                *lenses.entry(lens.clone()).or_default() += 1;
+            }
+        }
+        Equipment {
+            cameras: cameras
+                .into_iter()
+                .map(|(name, count)| EquipmentCount {
+                    url: GalleryFilter::by_camera(&name).url(),
+                    name,
+                    count,
+                })
+                .collect(),
+            lenses: lenses
+                .into_iter()
+                .map(|(name, count)| EquipmentCount {
+                    url: GalleryFilter::by_lens(&name).url(),
+                    name,
+                    count,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cameras.is_empty() && self.lenses.is_empty()
+    }
+}
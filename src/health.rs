@@ -0,0 +1,93 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::AppState;
+
+/// a project or a gallery image that failed to load and is missing from the site; the details
+/// are only logged, the public endpoints report the counts
+#[derive(Debug, Clone)]
+pub struct LoadFailure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+impl std::fmt::Display for LoadFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+#[derive(Serialize)]
+struct ItemCounts {
+    loaded: usize,
+    failed: usize,
+    /// not yet processed, only gallery images are loaded in background
+    pending: usize,
+}
+
+#[derive(Serialize)]
+struct Health {
+    /// whether the site is serving the whole content
+    ready: bool,
+    projects: ItemCounts,
+    gallery: ItemCounts,
+    music: ItemCounts,
+    audio: ItemCounts,
+}
+
+fn health(state: &AppState) -> Health {
+    let catalog = state.project_catalog.load();
+    let gallery = state.gallery.load();
+    let audio = state.audio_catalog.load();
+    let failed = catalog.failed.len()
+        + gallery.failed.len()
+        + state.music_catalog.failed.len()
+        + audio.failed.len();
+    Health {
+        // in strict mode any failure (e.g. after hot reloading) means the site is broken,
+        // otherwise we are fine serving what we have
+        ready: gallery.pending == 0 && !audio.loading && (!state.strict || failed == 0),
+        projects: ItemCounts {
+            loaded: catalog.projects.len(),
+            failed: catalog.failed.len(),
+            pending: 0,
+        },
+        gallery: ItemCounts {
+            loaded: gallery.size(),
+            failed: gallery.failed.len(),
+            pending: gallery.pending,
+        },
+        music: ItemCounts {
+            loaded: state.music_catalog.entries.len(),
+            failed: state.music_catalog.failed.len(),
+            pending: 0,
+        },
+        audio: ItemCounts {
+            loaded: audio.tracks.len(),
+            failed: audio.failed.len(),
+            pending: usize::from(audio.loading),
+        },
+    }
+}
+
+/// liveness probe: the server is up, whatever the state of the content
+pub async fn healthz(State(state): State<AppState>) -> Response {
+    Json(health(&state)).into_response()
+}
+
+/// readiness probe: the content is fully loaded
+pub async fn readyz(State(state): State<AppState>) -> Response {
+    let health = health(&state);
+    let status = if health.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(health)).into_response()
+}
@@ -0,0 +1,183 @@
+use image::{codecs, imageops::FilterType, DynamicImage, GenericImageView, ImageEncoder};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImagingError {
+    #[error("Failed to decode image {path:?}: {source}")]
+    Decode {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    #[error("Failed to encode image {path:?}: {source}")]
+    Encode {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    #[error("Failed to access {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl ImagingError {
+    pub fn io(path: &Path) -> impl FnOnce(io::Error) -> ImagingError + '_ {
+        |source| ImagingError::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn decode(path: &Path) -> impl FnOnce(image::ImageError) -> ImagingError + '_ {
+        |source| ImagingError::Decode {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn encode(path: &Path) -> impl FnOnce(image::ImageError) -> ImagingError + '_ {
+        |source| ImagingError::Encode {
+            path: path.to_owned(),
+            source,
+        }
+    }
+}
+
+/// formats responsive image variants are encoded in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VariantFormat {
+    Avif,
+    Webp,
+    Jpeg,
+}
+
+/// most efficient formats first, as browsers pick the first `<source>` they support; JPEG is
+/// the fallback every browser understands
+pub const VARIANT_FORMATS: [VariantFormat; 3] = [
+    VariantFormat::Avif,
+    VariantFormat::Webp,
+    VariantFormat::Jpeg,
+];
+
+/// formats for the `<source>` elements of `<picture>`, the fallback goes to `<img>` itself
+pub const MODERN_FORMATS: [VariantFormat; 2] = [VariantFormat::Avif, VariantFormat::Webp];
+
+/// rav1e speed preset, from 1 (slowest, best compression) to 10
+const AVIF_SPEED: u8 = 8;
+
+impl VariantFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            VariantFormat::Avif => "avif",
+            VariantFormat::Webp => "webp",
+            VariantFormat::Jpeg => "jpg",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            VariantFormat::Avif => "image/avif",
+            VariantFormat::Webp => "image/webp",
+            VariantFormat::Jpeg => "image/jpeg",
+        }
+    }
+
+    /// encodes the image with the given quality (0-100); alpha channel is kept, except for JPEG
+    pub fn save(&self, img: &DynamicImage, quality: u8, path: &Path) -> Result<(), ImagingError> {
+        let (width, height) = img.dimensions();
+        let create = || {
+            File::create(path)
+                .map(BufWriter::new)
+                .map_err(ImagingError::io(path))
+        };
+        let has_alpha = img.color().has_alpha() && *self != VariantFormat::Jpeg;
+        let (pixels, color_type) = if has_alpha {
+            (img.to_rgba8().into_raw(), image::ExtendedColorType::Rgba8)
+        } else {
+            (img.to_rgb8().into_raw(), image::ExtendedColorType::Rgb8)
+        };
+        let result = match self {
+            VariantFormat::Avif => {
+                codecs::avif::AvifEncoder::new_with_speed_quality(create()?, AVIF_SPEED, quality)
+                    .write_image(&pixels, width, height, color_type)
+            }
+            VariantFormat::Jpeg => codecs::jpeg::JpegEncoder::new_with_quality(create()?, quality)
+                .write_image(&pixels, width, height, color_type),
+            VariantFormat::Webp => {
+                let encoder = if has_alpha {
+                    webp::Encoder::from_rgba(&pixels, width, height)
+                } else {
+                    webp::Encoder::from_rgb(&pixels, width, height)
+                };
+                let encoded = encoder.encode(quality as f32);
+                return fs::write(path, &*encoded).map_err(ImagingError::io(path));
+            }
+        };
+        result.map_err(ImagingError::encode(path))
+    }
+}
+
+/// widths from the ladder smaller than the image's own width, plus the width itself, so that
+/// images are never upscaled
+pub fn variant_widths(ladder: &[u32], width: u32) -> Vec<u32> {
+    let mut widths: Vec<u32> = ladder.iter().copied().filter(|&w| w < width).collect();
+    widths.push(width);
+    widths
+}
+
+pub fn variant_filename(width: u32, format: VariantFormat) -> String {
+    format!("{}.{}", width, format.extension())
+}
+
+/// downscales the image to each of the widths and saves it in all the variant formats into
+/// `dir`, named as `<width>.<ext>`
+pub fn save_variants(
+    img: &DynamicImage,
+    widths: &[u32],
+    quality: u8,
+    dir: &Path,
+) -> Result<(), ImagingError> {
+    fs::create_dir_all(dir).map_err(ImagingError::io(dir))?;
+    for &width in widths {
+        let resized = if width < img.width() {
+            img.resize(width, u32::MAX, FilterType::Lanczos3)
+        } else {
+            img.clone()
+        };
+        for format in VARIANT_FORMATS {
+            format.save(
+                &resized,
+                quality,
+                &dir.join(variant_filename(width, format)),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// `srcset` attribute value listing variants of a single format under the URL prefix
+pub fn srcset(url_prefix: &str, widths: &[u32], format: VariantFormat) -> String {
+    widths
+        .iter()
+        .map(|&w| format!("{}/{} {}w", url_prefix, variant_filename(w, format), w))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// escapes characters that can't appear in URL paths as-is, most notably in `srcset`, where
+/// spaces and commas are separators
+pub fn escape_url_path(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                escaped.push(b as char)
+            }
+            _ => escaped.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    escaped
+}
@@ -1,187 +1,536 @@
+use arc_swap::ArcSwap;
 use askama::Template;
 use askama_axum::IntoResponse;
+use audio::{AudioCatalog, AudioTrack};
 use axum::{
     extract::{Path, Query, State},
     http::{header, StatusCode},
+    middleware,
     response::Response,
     routing::get,
     Router,
 };
-use gallery::Gallery;
-use project::{Project, ProjectTag, TagGroups};
-use rand::seq::SliceRandom;
+use gallery::{Gallery, ImageLocks, LoadOptions, ProcessingParams};
+use galleryalbums::Album;
+use gallerycache::CacheManifest;
+use galleryexif::{Equipment, GalleryFilter};
+use jiff::Timestamp;
+use music::{MusicCatalog, MusicEntry};
+use project::{Project, TagCounts, TagFilter, TagGroups};
+use rand::seq::IteratorRandom;
 use rand::thread_rng;
-use std::{cmp, collections::HashMap, env, path};
+use std::{
+    collections::HashMap,
+    env,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 use tower_http::trace::TraceLayer;
-use tower_http::{services::ServeDir, set_header::SetResponseHeader};
+use tower_http::{
+    services::ServeDir,
+    set_header::{SetResponseHeader, SetResponseHeaderLayer},
+};
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
+use transcode::AudioFiles;
 
-use templates::ProjectHyperlink;
+use errorpage::{ErrorPage, Suggestion};
+use templates::{ProjectHyperlink, SocialMeta};
 
+mod api;
+mod audio;
+mod check;
 mod colorpalette;
 mod date;
+mod errorpage;
+mod export;
+mod feed;
 mod gallery;
+mod galleryalbums;
+mod gallerycache;
+mod galleryexif;
+mod health;
+mod html;
+mod imaging;
+mod music;
+mod preview;
 mod project;
+mod projectmedia;
+mod search;
+mod sitemap;
+mod socialcard;
 mod templates;
+mod transcode;
+mod watch;
 
 #[derive(Clone)]
 struct AppState {
-    project_catalog: project::ProjectCatalog,
-    gallery: gallery::Gallery,
+    project_catalog: Arc<ArcSwap<project::ProjectCatalog>>,
+    gallery: Arc<ArcSwap<gallery::Gallery>>,
+    music_catalog: Arc<MusicCatalog>,
+    audio_catalog: Arc<ArcSwap<AudioCatalog>>,
+    audio_files: Arc<AudioFiles>,
+    /// public URL of the site, used to build absolute links
+    base_url: String,
+    /// secret for viewing unpublished projects as `/projects/<slug>?preview=<token>`; previews
+    /// are disabled without it
+    preview_token: Option<String>,
+    /// any content failing to load is fatal
+    strict: bool,
+    /// paths crawlers are asked not to visit in `robots.txt`
+    robots_disallow: Vec<String>,
+}
+
+/// source content and generated media locations
+#[derive(Clone)]
+struct ContentDirs {
+    static_dir: PathBuf,
+    projects_dir: PathBuf,
+    project_media_dir: PathBuf,
+    gallery_dir: PathBuf,
+    gallery_stdmedia_dir: PathBuf,
+    gallery_thumbnails_dir: PathBuf,
+    /// responsive variants of gallery images, in subdirs named after the originals
+    gallery_variants_dir: PathBuf,
+    audio_dir: PathBuf,
+    /// track info and cover art extracted from the audio files
+    audio_media_dir: PathBuf,
+    /// transcoded variants of the audio files
+    audio_cache_dir: PathBuf,
+    music_dir: PathBuf,
+}
+
+enum Command {
+    Serve,
+    Build { out_dir: PathBuf },
+    Check,
+}
+
+impl Command {
+    fn parse(args: &[String]) -> Result<Command, String> {
+        match args {
+            [] => Ok(Command::Serve),
+            [cmd] if cmd == "serve" => Ok(Command::Serve),
+            [cmd, rest @ ..] if cmd == "build" => match rest {
+                [] => Ok(Command::Build {
+                    out_dir: PathBuf::from("dist"),
+                }),
+                [flag, out_dir] if flag == "--out" => Ok(Command::Build {
+                    out_dir: PathBuf::from(out_dir),
+                }),
+                _ => Err("usage: build [--out <dir>]".to_owned()),
+            },
+            [cmd] if cmd == "check" => Ok(Command::Check),
+            _ => Err(format!("unknown command: {}", args.join(" "))),
+        }
+    }
 }
 
+const USAGE: &str = "usage: nj-vs-vh-page [serve | build [--out <dir>] | check]";
+
 #[tokio::main]
 async fn main() {
-    let is_dev = env::var("DEV").map(|v| v.len() > 0).unwrap_or(false);
+    let is_dev = env::var("DEV").map(|v| !v.is_empty()).unwrap_or(false);
     let is_debug = env::var("DEBUG").is_ok();
+    let is_strict = env::var("STRICT").map(|v| !v.is_empty()).unwrap_or(false);
 
     let log_level = if is_debug { Level::DEBUG } else { Level::INFO };
     let subscriber = FmtSubscriber::builder().with_max_level(log_level).finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
     tracing::info!("Debug = {}, log level = {}", is_debug, log_level);
 
-    let static_dir_string = env::var("STATIC_DIR").unwrap_or("static".to_owned());
-    let static_dir = std::path::Path::new(&static_dir_string);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match Command::parse(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let static_dir = PathBuf::from(env::var("STATIC_DIR").unwrap_or("static".to_owned()));
     tracing::info!("Serving static files from {:?}", &static_dir);
-    let project_media_dir = static_dir.join("project-media");
-    if let Ok(dir_iter) = project_media_dir.read_dir() {
-        dir_iter.for_each(|file| {
-            if let Ok(file) = file {
-                if let Err(e) = std::fs::remove_file(file.path()) {
-                    tracing::warn!("Error deleting stale media file {:?}: {}", file.path(), e);
-                }
-            }
-        })
+    let dirs = ContentDirs {
+        project_media_dir: static_dir.join("project-media"),
+        gallery_thumbnails_dir: static_dir.join("gallery-thumbnails"),
+        gallery_stdmedia_dir: static_dir.join("gallery-media"),
+        gallery_variants_dir: static_dir.join("gallery-variants"),
+        audio_media_dir: static_dir.join("audio-media"),
+        audio_cache_dir: static_dir.join("audio-transcoded"),
+        static_dir,
+        projects_dir: PathBuf::from(env::var("PROJECTS_DIR").unwrap_or("projects".to_owned())),
+        gallery_dir: PathBuf::from(env::var("GALLERY_DIR").unwrap_or("gallery".to_owned())),
+        audio_dir: PathBuf::from(env::var("AUDIO_DIR").unwrap_or("audio".to_owned())),
+        music_dir: PathBuf::from(env::var("MUSIC_DIR").unwrap_or("music".to_owned())),
+    };
+
+    if let Command::Check = command {
+        std::process::exit(if check::check(&dirs) { 0 } else { 1 });
     }
-    if let Err(e) = std::fs::create_dir_all(&project_media_dir) {
-        tracing::error!("Error creating media dir {:?}: {}", project_media_dir, e);
+
+    if let Err(e) = std::fs::create_dir_all(&dirs.project_media_dir) {
+        tracing::error!(
+            "Error creating media dir {:?}: {}",
+            dirs.project_media_dir,
+            e
+        );
         return;
     };
-    let gallery_thumbnails_dir = static_dir.join("gallery-thumbnails");
-    if let Err(e) = std::fs::create_dir_all(&gallery_thumbnails_dir) {
+    if let Err(e) = std::fs::create_dir_all(&dirs.gallery_thumbnails_dir) {
         tracing::error!(
             "Error creating thumbnails dir {:?}: {}",
-            &gallery_thumbnails_dir,
+            &dirs.gallery_thumbnails_dir,
             e
         );
         return;
     };
-    let gallery_stdmedia_dir = static_dir.join("gallery-media");
-    if let Err(e) = std::fs::create_dir_all(&gallery_stdmedia_dir) {
+    if let Err(e) = std::fs::create_dir_all(&dirs.gallery_stdmedia_dir) {
         tracing::error!(
             "Error creating gallery media dir {:?}: {}",
-            &gallery_stdmedia_dir,
+            &dirs.gallery_stdmedia_dir,
+            e
+        );
+        return;
+    };
+    if let Err(e) = std::fs::create_dir_all(&dirs.gallery_variants_dir) {
+        tracing::error!(
+            "Error creating gallery variants dir {:?}: {}",
+            &dirs.gallery_variants_dir,
             e
         );
         return;
     };
 
-    let projects_dir = env::var("PROJECTS_DIR").unwrap_or("projects".to_owned());
-    let catalog_res =
-        project::ProjectCatalog::load(path::Path::new(&projects_dir), &project_media_dir);
+    if let Err(e) = std::fs::create_dir_all(&dirs.audio_media_dir) {
+        tracing::error!(
+            "Error creating audio media dir {:?}: {}",
+            &dirs.audio_media_dir,
+            e
+        );
+        return;
+    };
+
+    let catalog_res = project::ProjectCatalog::load(&dirs.projects_dir, &dirs.project_media_dir);
     if let Err(e) = catalog_res {
         tracing::error!("Failed to load project catalog: {}", e);
         return;
     }
     let catalog = catalog_res.unwrap();
     tracing::info!("Loaded project catalog: {}", &catalog);
+    if let Err(e) = catalog.remove_stale_media(&dirs.project_media_dir) {
+        tracing::warn!("Failed to remove stale project media: {}", e);
+    }
+
+    tracing::info!("Serving gallery files from {:?}", &dirs.gallery_dir);
+    let gallery_sources = match gallery::sources(&dirs.gallery_dir) {
+        Ok(sources) => sources,
+        Err(e) => {
+            tracing::error!("Failed to load gallery: {}", e);
+            return;
+        }
+    };
+    let mut processing_params = ProcessingParams::default();
+    if let Ok(widths) = env::var("GALLERY_WIDTHS") {
+        match widths.split(',').map(|w| w.trim().parse()).collect() {
+            Ok(widths) => processing_params.variant_widths = widths,
+            Err(e) => {
+                tracing::error!("Invalid GALLERY_WIDTHS {:?}: {}", widths, e);
+                return;
+            }
+        }
+    }
+    let processing_params = Arc::new(processing_params);
+    let gallery_cache = Arc::new(Mutex::new(CacheManifest::load(&dirs.gallery_dir)));
+    let gallery_locks = Arc::new(ImageLocks::default());
+    let gallery = Arc::new(ArcSwap::from_pointee(Gallery::default()));
+    // images are published as they are processed, the server doesn't wait for all of them
+    let gallery_loading = {
+        let dirs = dirs.clone();
+        let params = processing_params.clone();
+        let cache = gallery_cache.clone();
+        let locks = gallery_locks.clone();
+        let gallery = gallery.clone();
+        let options = LoadOptions {
+            ignore_cache: !env::var("GALLERY_IGNORE_CACHE")
+                .unwrap_or("".to_owned())
+                .is_empty(),
+            workers: env::var("GALLERY_WORKERS")
+                .ok()
+                .and_then(|w| w.parse().ok())
+                .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+        };
+        tokio::task::spawn_blocking(move || {
+            gallery::load(
+                gallery_sources,
+                &dirs,
+                &params,
+                &cache,
+                &locks,
+                &options,
+                &gallery,
+            )
+        })
+    };
+
+    let music_catalog = match MusicCatalog::load(&dirs.music_dir) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            tracing::error!("Failed to load music catalog: {}", e);
+            return;
+        }
+    };
+    tracing::info!("Loaded music catalog: {}", &music_catalog);
 
-    let gallery_dir_string = env::var("GALLERY_DIR").unwrap_or("gallery".to_owned());
-    let gallery_dir = std::path::Path::new(&gallery_dir_string);
-    tracing::info!("Serving gallery files from {:?}", &gallery_dir);
-    let gr = Gallery::load(
-        gallery_dir,
-        &gallery_stdmedia_dir,
-        &gallery_thumbnails_dir,
-        env::var("GALLERY_IGNORE_CACHE")
-            .unwrap_or("".to_owned())
-            .len()
-            > 0,
+    let bitrates = env::var("AUDIO_BITRATES").unwrap_or("64,96,128,192".to_owned());
+    let bitrates: Vec<u32> = match bitrates.split(',').map(|b| b.trim().parse()).collect() {
+        Ok(bitrates) => bitrates,
+        Err(e) => {
+            tracing::error!("Invalid AUDIO_BITRATES {:?}: {}", bitrates, e);
+            return;
+        }
+    };
+    let default_bitrate = env::var("AUDIO_BITRATE").unwrap_or("128".to_owned());
+    let default_bitrate = match default_bitrate.parse() {
+        Ok(bitrate) => bitrate,
+        Err(e) => {
+            tracing::error!("Invalid AUDIO_BITRATE {:?}: {}", default_bitrate, e);
+            return;
+        }
+    };
+    let audio_files = AudioFiles::new(
+        dirs.audio_dir.clone(),
+        dirs.audio_cache_dir.clone(),
+        PathBuf::from(env::var("FFMPEG").unwrap_or("ffmpeg".to_owned())),
+        bitrates,
+        default_bitrate,
     );
-    if let Err(e) = gr {
-        tracing::error!("Failed to load gallery: {}", e);
+    let audio_files = Arc::new(audio_files);
+    tracing::info!("Serving audio files from {:?}", &dirs.audio_dir);
+    let audio_catalog = Arc::new(ArcSwap::from_pointee(AudioCatalog::loading()));
+    // decoding every track takes a while on the first run, the server doesn't wait for it
+    let audio_loading = {
+        let dirs = dirs.clone();
+        let audio_catalog = audio_catalog.clone();
+        let audio_files = audio_files.clone();
+        tokio::task::spawn_blocking(move || {
+            let catalog = match AudioCatalog::load(&dirs.audio_dir, &dirs.audio_media_dir) {
+                Ok(catalog) => catalog,
+                Err(e) => {
+                    tracing::error!("Failed to load audio catalog: {}", e);
+                    AudioCatalog {
+                        failed: vec![health::LoadFailure {
+                            path: dirs.audio_dir.clone(),
+                            error: e.to_string(),
+                        }],
+                        ..Default::default()
+                    }
+                }
+            };
+            tracing::info!("Loaded audio catalog: {}", &catalog);
+            audio_files.remove_stale(&catalog);
+            audio_catalog.store(Arc::new(catalog));
+        })
+    };
+
+    let state = AppState {
+        project_catalog: Arc::new(ArcSwap::from_pointee(catalog)),
+        gallery,
+        music_catalog: Arc::new(music_catalog),
+        audio_catalog,
+        audio_files,
+        base_url: env::var("BASE_URL")
+            .unwrap_or("https://nj-vs-vh.name".to_owned())
+            .trim_end_matches('/')
+            .to_owned(),
+        preview_token: env::var("PREVIEW_TOKEN").ok().filter(|t| !t.is_empty()),
+        strict: is_strict,
+        robots_disallow: env::var("ROBOTS_DISALLOW")
+            .unwrap_or("/search,/api/".to_owned())
+            .split(',')
+            .map(|path| path.trim().to_owned())
+            .filter(|path| !path.is_empty())
+            .collect(),
+    };
+
+    // the build and strict mode need all the content loaded upfront
+    if is_strict || matches!(command, Command::Build { .. }) {
+        if let Err(e) = gallery_loading.await {
+            tracing::error!("Failed to load gallery: {}", e);
+            std::process::exit(1);
+        }
+        if let Err(e) = audio_loading.await {
+            tracing::error!("Failed to load audio catalog: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if is_strict {
+        let catalog = state.project_catalog.load();
+        let gallery = state.gallery.load();
+        let audio_catalog = state.audio_catalog.load();
+        let failures: Vec<String> = catalog
+            .failed
+            .iter()
+            .chain(gallery.failed.iter())
+            .chain(state.music_catalog.failed.iter())
+            .chain(audio_catalog.failed.iter())
+            .map(|f| f.to_string())
+            .collect();
+        if !failures.is_empty() {
+            tracing::error!(
+                "Strict mode: refusing to start, {} item(s) failed to load:\n{}",
+                failures.len(),
+                failures.join("\n")
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let static_content_cache = if !is_dev { "max-age=300" } else { "no-cache" };
+    let app = router(state.clone(), &dirs, static_content_cache);
+
+    if let Command::Build { out_dir } = command {
+        if let Err(e) = export::export(app, &state, &dirs, &out_dir).await {
+            tracing::error!("Failed to export static site into {:?}: {}", out_dir, e);
+            std::process::exit(1);
+        }
         return;
     }
-    let gallery = gr.unwrap();
-    tracing::info!("Loaded gallery: {}", &gallery);
 
-    let audio_dir_string = env::var("AUDIO_DIR").unwrap_or("audio".to_owned());
-    let audio_dir = std::path::Path::new(&audio_dir_string);
-    tracing::info!("Serving audio files from {:?}", &gallery_dir);
+    // keeping the watcher alive for the lifetime of the server
+    let _watcher = match watch::watch(
+        &dirs,
+        state.project_catalog.clone(),
+        state.gallery.clone(),
+        processing_params,
+        gallery_cache,
+        gallery_locks,
+    ) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            tracing::warn!(
+                "Failed to start watching content dirs, hot reload is disabled: {}",
+                e
+            );
+            None
+        }
+    };
 
-    let static_content_cache = if !is_dev { "max-age=300" } else { "no-cache" };
-    let app = Router::new()
+    let port = env::var("PORT").unwrap_or("3284".to_owned());
+    let host = env::var("HOST").unwrap_or("0.0.0.0".to_owned());
+    let addr = format!("{}:{}", host, port);
+    tracing::info!("Port: {}, host: {}, address: {}", port, host, addr);
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    tracing::info!("listening on {}", listener.local_addr().unwrap());
+    axum::serve(listener, app).await.unwrap();
+}
+
+fn router(state: AppState, dirs: &ContentDirs, static_content_cache: &'static str) -> Router {
+    Router::new()
         .route("/", get(index))
         .route("/projects", get(project_list))
         .route("/projects/", get(project_list))
+        .route("/projects/feed.xml", get(feed::projects_atom))
+        .route("/projects/rss.xml", get(feed::projects_rss))
         .route("/projects/:slug", get(project_page))
+        .route("/projects/cards/:filename", get(project_card))
+        .route("/search", get(search))
         .route("/tags/", get(tag_list))
         .route("/tags", get(tag_list))
-        .route("/music", get(music))
+        .route("/music", get(music_page))
+        .route("/music/tracks", get(track_list))
+        .route("/music/tracks/", get(track_list))
+        .route("/music/tracks/:slug", get(track_page))
         .route("/gallery", get(gallery_page))
+        .route("/gallery/feed.xml", get(feed::gallery_atom))
+        .route("/gallery/rss.xml", get(feed::gallery_rss))
+        .route("/gallery/albums", get(gallery_albums))
+        .route("/gallery/albums/", get(gallery_albums))
+        .route("/gallery/albums/:slug", get(gallery_album))
         .route("/gallery/:slug", get(gallery_image))
+        .route("/sitemap.xml", get(sitemap::sitemap))
+        .route("/robots.txt", get(sitemap::robots))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
         .nest_service(
             "/static",
             SetResponseHeader::if_not_present(
-                ServeDir::new(static_dir),
+                ServeDir::new(&dirs.static_dir),
                 header::CACHE_CONTROL,
-                header::HeaderValue::from_static(&static_content_cache),
+                header::HeaderValue::from_static(static_content_cache),
             ),
         )
         .nest_service(
             "/gallery/full",
             SetResponseHeader::if_not_present(
-                ServeDir::new(gallery_dir),
+                ServeDir::new(&dirs.gallery_dir),
                 header::CACHE_CONTROL,
-                header::HeaderValue::from_static(&static_content_cache),
+                header::HeaderValue::from_static(static_content_cache),
             ),
         )
         .nest_service(
             "/gallery/thumbnails",
             SetResponseHeader::if_not_present(
-                ServeDir::new(gallery_thumbnails_dir),
+                ServeDir::new(&dirs.gallery_thumbnails_dir),
                 header::CACHE_CONTROL,
-                header::HeaderValue::from_static(&static_content_cache),
+                header::HeaderValue::from_static(static_content_cache),
             ),
         )
         .nest_service(
             "/gallery/media",
             SetResponseHeader::if_not_present(
-                ServeDir::new(gallery_stdmedia_dir),
+                ServeDir::new(&dirs.gallery_stdmedia_dir),
                 header::CACHE_CONTROL,
-                header::HeaderValue::from_static(&static_content_cache),
+                header::HeaderValue::from_static(static_content_cache),
             ),
         )
         .nest_service(
-            "/projects/media",
+            "/gallery/variants",
             SetResponseHeader::if_not_present(
-                ServeDir::new(project_media_dir),
+                ServeDir::new(&dirs.gallery_variants_dir),
                 header::CACHE_CONTROL,
-                header::HeaderValue::from_static(&static_content_cache),
+                header::HeaderValue::from_static(static_content_cache),
             ),
         )
+        .route(
+            "/projects/media/:slug/*file",
+            get(preview::project_media).layer(SetResponseHeaderLayer::if_not_present(
+                header::CACHE_CONTROL,
+                header::HeaderValue::from_static(static_content_cache),
+            )),
+        )
+        // the generated dirs are kept in the static dir, project media must only be reachable
+        // through the route above
+        .route("/static/project-media/*file", get(errorpage::not_found))
         .nest_service(
-            "/audio",
+            "/music/tracks/media",
             SetResponseHeader::if_not_present(
-                ServeDir::new(audio_dir),
+                ServeDir::new(&dirs.audio_media_dir),
                 header::CACHE_CONTROL,
-                header::HeaderValue::from_static(&static_content_cache),
+                header::HeaderValue::from_static(static_content_cache),
             ),
         )
+        .route(
+            "/audio/*file",
+            get(transcode::audio_file).layer(SetResponseHeaderLayer::if_not_present(
+                header::CACHE_CONTROL,
+                header::HeaderValue::from_static(static_content_cache),
+            )),
+        )
+        .fallback(errorpage::not_found)
+        // applied before nesting the API, which returns bare statuses
+        .layer(middleware::map_response(errorpage::render_bare_errors))
+        .nest("/api/v1", api::router())
         .layer(TraceLayer::new_for_http())
-        .with_state(AppState {
-            project_catalog: catalog,
-            gallery,
-        });
+        .with_state(state)
+}
 
-    let port = env::var("PORT").unwrap_or("3284".to_owned());
-    let host = env::var("HOST").unwrap_or("0.0.0.0".to_owned());
-    let addr = format!("{}:{}", host, port);
-    tracing::info!("Port: {}, host: {}, address: {}", port, host, addr);
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    tracing::info!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+/// 301, unlike `Redirect::permanent`, which is 308
+fn moved_permanently(location: &str) -> Response {
+    (
+        StatusCode::MOVED_PERMANENTLY,
+        [(header::LOCATION, location.to_owned())],
+    )
+        .into_response()
 }
 
 // index
@@ -191,13 +540,14 @@ struct Index<'a> {
     selected_project_hyperlinks: Vec<ProjectHyperlink<'a>>,
 }
 
-async fn index<'a>(State(state): State<AppState>) -> Response {
+async fn index(State(state): State<AppState>) -> Response {
+    let catalog = state.project_catalog.load();
     let mut rng = thread_rng();
     Index {
-        selected_project_hyperlinks: state
-            .project_catalog
-            .projects
+        selected_project_hyperlinks: catalog
+            .published()
             .choose_multiple(&mut rng, 3)
+            .into_iter()
             .map(|p| ProjectHyperlink { p })
             .collect(),
     }
@@ -208,32 +558,24 @@ async fn index<'a>(State(state): State<AppState>) -> Response {
 #[template(path = "project_list.html")]
 struct ProjectList<'a> {
     project_hyperlinks: Vec<ProjectHyperlink<'a>>,
-    tag_filter: Option<ProjectTag>,
+    filter: TagFilter,
+    tag_counts: TagCounts,
 }
 
-async fn project_list<'a>(
+async fn project_list(
     State(state): State<AppState>,
-    Query(query): Query<HashMap<String, String>>,
-) -> Result<Response, StatusCode> {
-    let tag_filter = match query.get("tag") {
-        None => None,
-        Some(t) => Some(ProjectTag::parse(t).map_err(|_| StatusCode::BAD_REQUEST)?),
-    };
+    Query(query): Query<Vec<(String, String)>>,
+) -> Result<Response, ErrorPage> {
+    let catalog = state.project_catalog.load();
+    let filter = ErrorPage::tag_filter(&query, &catalog)?;
     Ok(ProjectList {
-        project_hyperlinks: state
-            .project_catalog
-            .projects
-            .iter()
-            .filter(|p| {
-                if let Some(tag_filter) = &tag_filter {
-                    p.metadata.tags.contains(tag_filter)
-                } else {
-                    true
-                }
-            })
+        project_hyperlinks: catalog
+            .published()
+            .filter(|p| filter.matches(p))
             .map(|p| ProjectHyperlink { p })
             .collect(),
-        tag_filter,
+        tag_counts: catalog.tag_counts(&filter),
+        filter,
     }
     .into_response())
 }
@@ -244,39 +586,236 @@ async fn project_list<'a>(
 #[template(path = "project.html")]
 struct ProjectPage<'a> {
     project: &'a Project,
+    /// unpublished project shown with the preview token
+    preview: bool,
+    social: SocialMeta,
 }
 
-async fn project_page<'a>(
+impl ProjectPage<'_> {
+    /// `preview_token` is given for unpublished projects, whose generated cards are
+    /// not served without it
+    fn new<'a>(
+        project: &'a Project,
+        preview_token: Option<&str>,
+        base_url: &str,
+    ) -> ProjectPage<'a> {
+        let mut image_url = format!("{}{}", base_url, project.social_image.url);
+        if let (Some(token), Some(_)) = (preview_token, &project.social_card) {
+            image_url.push_str("?preview=");
+            image_url.extend(form_urlencoded::byte_serialize(token.as_bytes()));
+        }
+        ProjectPage {
+            project,
+            preview: preview_token.is_some(),
+            social: SocialMeta {
+                title: project.metadata.title.clone(),
+                description: project.description.clone(),
+                url: format!("{}/projects/{}", base_url, project.metadata.slug),
+                image: socialcard::SocialImage {
+                    url: image_url,
+                    ..project.social_image.clone()
+                },
+            },
+        }
+    }
+}
+
+async fn project_page(
     State(state): State<AppState>,
     Path(slug): Path<String>,
-) -> Result<Response, StatusCode> {
-    let project_match = state.project_catalog.find(&slug);
-    if let Some(project) = project_match {
-        Ok(ProjectPage { project }.into_response())
-    } else {
-        Err(StatusCode::NOT_FOUND)
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, ErrorPage> {
+    let catalog = state.project_catalog.load();
+    if let Some(project) = catalog.find_published(&slug) {
+        return Ok(ProjectPage::new(project, None, &state.base_url).into_response());
     }
+    let preview_token = params
+        .get("preview")
+        .filter(|token| preview::is_valid(&state, token));
+    if let Some(project) = catalog.find_by_alias(&slug) {
+        if project.is_published(Timestamp::now()) {
+            return Ok(moved_permanently(&format!(
+                "/projects/{}",
+                project.metadata.slug
+            )));
+        } else if let Some(token) = preview_token {
+            return Ok(moved_permanently(&format!(
+                "/projects/{}?preview={}",
+                project.metadata.slug,
+                form_urlencoded::byte_serialize(token.as_bytes()).collect::<String>()
+            )));
+        }
+    }
+    match catalog.find(&slug) {
+        Some(project) if preview_token.is_some() => {
+            let token = preview_token.map(String::as_str);
+            let mut response = ProjectPage::new(project, token, &state.base_url).into_response();
+            // media is linked from the page without the token
+            if let Some(cookie) =
+                token.and_then(|t| preview::media_cookie(t, &project.metadata.slug))
+            {
+                response.headers_mut().insert(header::SET_COOKIE, cookie);
+            }
+            Ok(response)
+        }
+        _ => {
+            let suggestions = errorpage::closest(
+                &slug,
+                catalog
+                    .published()
+                    .map(|p| (p.metadata.slug.as_str(), p.metadata.slug.as_str())),
+            )
+            .into_iter()
+            .filter_map(|slug| catalog.find_published(slug))
+            .map(|p| Suggestion {
+                href: format!("/projects/{}", p.metadata.slug),
+                title: p.metadata.title.clone(),
+            })
+            .collect();
+            Err(
+                ErrorPage::not_found(format!("there is no project {:?}", slug))
+                    .with_suggestions(suggestions),
+            )
+        }
+    }
+}
+
+/// generated social preview image, for projects without a cover; unpublished ones need the
+/// preview token, same as their pages
+async fn project_card(
+    State(state): State<AppState>,
+    Path(filename): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, ErrorPage> {
+    let catalog = state.project_catalog.load();
+    let preview = params
+        .get("preview")
+        .is_some_and(|token| preview::is_valid(&state, token));
+    let card = filename
+        .strip_suffix(".png")
+        .and_then(|slug| {
+            if preview {
+                catalog.find(slug)
+            } else {
+                catalog.find_published(slug)
+            }
+        })
+        .and_then(|project| project.social_card.clone())
+        .ok_or_else(|| ErrorPage::not_found("there is no such image"))?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], card.to_vec()).into_response())
 }
 
 #[derive(Template)]
-#[template(path = "tag_list.html")]
-struct TagSearchPage<'a> {
-    tag_groups: &'a TagGroups,
+#[template(path = "search.html")]
+struct SearchPage<'a> {
+    query: String,
+    filter: TagFilter,
+    results: Vec<search::SearchResult<'a>>,
 }
 
-async fn tag_list(State(state): State<AppState>) -> Result<Response, StatusCode> {
-    Ok(TagSearchPage {
-        tag_groups: &state.project_catalog.tag_groups,
+async fn search(
+    State(state): State<AppState>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Response, ErrorPage> {
+    let query = params
+        .iter()
+        .find(|(key, _)| key == "q")
+        .map(|(_, q)| q.trim().to_owned())
+        .unwrap_or_default();
+    let catalog = state.project_catalog.load();
+    let filter = ErrorPage::tag_filter(&params, &catalog)?;
+
+    let results = search::search_projects(&catalog, &query, &filter);
+    Ok(SearchPage {
+        query,
+        filter,
+        results,
     }
     .into_response())
 }
 
+#[derive(Template)]
+#[template(path = "tag_list.html")]
+struct TagSearchPage {
+    tag_groups: TagGroups,
+}
+
+async fn tag_list(State(state): State<AppState>) -> Response {
+    let catalog = state.project_catalog.load();
+    TagSearchPage {
+        tag_groups: catalog.tag_groups(),
+    }
+    .into_response()
+}
+
 #[derive(Template)]
 #[template(path = "music.html")]
-struct MusicPage {}
+struct MusicPage<'a> {
+    entries: &'a [MusicEntry],
+    /// recordings are linked to their track pages
+    tracks: &'a AudioCatalog,
+}
+
+async fn music_page(State(state): State<AppState>) -> Response {
+    MusicPage {
+        entries: &state.music_catalog.entries,
+        tracks: &state.audio_catalog.load(),
+    }
+    .into_response()
+}
+
+#[derive(Template)]
+#[template(path = "track_list.html")]
+struct TrackList<'a> {
+    tracks: &'a [AudioTrack],
+}
 
-async fn music() -> MusicPage {
-    MusicPage {}
+async fn track_list(State(state): State<AppState>) -> Response {
+    TrackList {
+        tracks: &state.audio_catalog.load().tracks,
+    }
+    .into_response()
+}
+
+#[derive(Template)]
+#[template(path = "track.html")]
+struct TrackPage<'a> {
+    track: &'a AudioTrack,
+    sources: Vec<transcode::AudioSource>,
+    prev: Option<&'a AudioTrack>,
+    next: Option<&'a AudioTrack>,
+}
+
+async fn track_page(
+    State(state): State<AppState>,
+    Path(slug): Path<String>,
+) -> Result<Response, ErrorPage> {
+    let catalog = state.audio_catalog.load();
+    if let Some(track) = catalog.find(&slug) {
+        let (prev, next) = catalog.neighbours(&slug);
+        return Ok(TrackPage {
+            track,
+            sources: state.audio_files.sources(track),
+            prev,
+            next,
+        }
+        .into_response());
+    }
+    let suggestions = errorpage::closest(
+        &slug,
+        catalog
+            .tracks
+            .iter()
+            .map(|t| (t.slug.as_str(), t.slug.as_str())),
+    )
+    .into_iter()
+    .filter_map(|slug| catalog.find(slug))
+    .map(|t| Suggestion {
+        href: format!("/music/tracks/{}", t.slug),
+        title: t.title.clone(),
+    })
+    .collect();
+    Err(ErrorPage::not_found(format!("there is no track {:?}", slug)).with_suggestions(suggestions))
 }
 
 #[derive(Template)]
@@ -284,46 +823,165 @@ async fn music() -> MusicPage {
 struct GalleryPage<'a> {
     page: usize,
     total_pages: usize,
-    images_by_year: Vec<(String, &'a [gallery::GalleryImage])>,
+    images_by_year: Vec<(String, &'a [&'a gallery::GalleryImage])>,
+    has_albums: bool,
+    filter: GalleryFilter,
+    equipment: Equipment,
 }
 
 const GALLERY_PAGE_SIZE: usize = 25;
 
-async fn gallery_page<'a>(
+async fn gallery_page(
     State(state): State<AppState>,
     Query(params): Query<HashMap<String, String>>,
-) -> Result<Response, StatusCode> {
+) -> Response {
     let page: usize = params
         .get("p")
         .map_or(1, |page_str| page_str.parse().unwrap_or(1));
-    let pageidx = page.saturating_sub(1);
 
-    let start_idx = GALLERY_PAGE_SIZE * pageidx;
-    let end_idx = cmp::min(GALLERY_PAGE_SIZE * (pageidx + 1), state.gallery.size());
-    Ok(GalleryPage {
+    let filter = GalleryFilter::from_query(&params);
+
+    let gallery = state.gallery.load();
+    let images = gallery.filtered(&filter);
+    GalleryPage {
         page,
-        total_pages: state.gallery.total_pages(GALLERY_PAGE_SIZE),
-        images_by_year: state.gallery.images[start_idx..end_idx]
+        total_pages: gallery::total_pages(images.len(), GALLERY_PAGE_SIZE),
+        images_by_year: gallery::page(&images, page, GALLERY_PAGE_SIZE)
             .chunk_by(|i1, i2| i1.month_year() == i2.month_year())
             .map(|photos| (photos[0].month_year(), photos))
             .collect(),
+        has_albums: !gallery.albums.is_empty(),
+        filter,
+        equipment: Equipment::count(&gallery.images),
     }
-    .into_response())
+    .into_response()
+}
+
+struct AlbumSummary<'a> {
+    album: &'a Album,
+    cover: Option<&'a gallery::GalleryImage>,
+    size: usize,
+}
+
+#[derive(Template)]
+#[template(path = "gallery_albums.html")]
+struct GalleryAlbumsPage<'a> {
+    albums: Vec<AlbumSummary<'a>>,
+}
+
+async fn gallery_albums(State(state): State<AppState>) -> Response {
+    let gallery = state.gallery.load();
+    GalleryAlbumsPage {
+        albums: gallery
+            .albums
+            .iter()
+            .map(|album| AlbumSummary {
+                album,
+                cover: gallery.album_cover(album),
+                size: gallery.album_images(album).len(),
+            })
+            .collect(),
+    }
+    .into_response()
+}
+
+#[derive(Template)]
+#[template(path = "gallery_album.html")]
+struct GalleryAlbumPage<'a> {
+    album: &'a Album,
+    images: Vec<&'a gallery::GalleryImage>,
+}
+
+async fn gallery_album(
+    State(state): State<AppState>,
+    Path(slug): Path<String>,
+) -> Result<Response, ErrorPage> {
+    let gallery = state.gallery.load();
+    if let Some(album) = gallery.album(&slug) {
+        return Ok(GalleryAlbumPage {
+            album,
+            images: gallery.album_images(album),
+        }
+        .into_response());
+    }
+    let suggestions = errorpage::closest(
+        &slug,
+        gallery
+            .albums
+            .iter()
+            .map(|a| (a.slug.as_str(), a.slug.as_str())),
+    )
+    .into_iter()
+    .filter_map(|slug| gallery.album(slug))
+    .map(|a| Suggestion {
+        href: format!("/gallery/albums/{}", a.slug),
+        title: a.title.clone(),
+    })
+    .collect();
+    Err(ErrorPage::not_found(format!("there is no album {:?}", slug)).with_suggestions(suggestions))
 }
 
 #[derive(Template)]
 #[template(path = "gallery_image.html")]
 struct GalleryImagePage<'a> {
     found: gallery::FoundGalleryImage<'a>,
+    social: SocialMeta,
 }
 
-async fn gallery_image<'a>(
+async fn gallery_image(
     State(state): State<AppState>,
     Path(slug): Path<String>,
-) -> Result<Response, StatusCode> {
-    if let Some(image) = state.gallery.find(&slug) {
-        Ok(GalleryImagePage { found: image }.into_response())
-    } else {
-        Err(StatusCode::NOT_FOUND)
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, ErrorPage> {
+    let gallery = state.gallery.load();
+    // navigation falls back to the whole gallery for images outside of the album
+    let album = params.get("album").and_then(|slug| gallery.album(slug));
+    let found = album
+        .and_then(|album| gallery.find_in_album(&slug, album))
+        .or_else(|| gallery.find(&slug));
+    if let Some(found) = found {
+        let image = found.image;
+        let url = imaging::escape_url_path(&image.filename);
+        let social = SocialMeta {
+            title: image.title.clone().unwrap_or(image.filename.clone()),
+            description: format!(
+                "{} in the gallery on Igor Vaiman's personal website",
+                image.filename
+            ),
+            url: format!("{}/gallery/{}", state.base_url, url),
+            image: socialcard::SocialImage {
+                url: format!("{}/gallery/media/{}", state.base_url, url),
+                width: image.width,
+                height: image.height,
+            },
+        };
+        return Ok(GalleryImagePage { found, social }.into_response());
     }
+    if let Some(current) = gallery.find_renamed(&slug) {
+        let query = album
+            .map(|album| format!("?album={}", album.slug))
+            .unwrap_or_default();
+        return Ok(moved_permanently(&format!(
+            "/gallery/{}{}",
+            imaging::escape_url_path(current),
+            query
+        )));
+    }
+    let suggestions = errorpage::closest(
+        &slug,
+        gallery
+            .images
+            .iter()
+            .map(|img| (img.filename.as_str(), img.filename.as_str())),
+    )
+    .into_iter()
+    .map(|filename| Suggestion {
+        href: format!("/gallery/{}", imaging::escape_url_path(filename)),
+        title: filename.to_owned(),
+    })
+    .collect();
+    Err(
+        ErrorPage::not_found(format!("there is no image {:?} in the gallery", slug))
+            .with_suggestions(suggestions),
+    )
 }
@@ -0,0 +1,212 @@
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::audio;
+use crate::health::LoadFailure;
+use crate::imaging;
+use crate::project::{Markdown, ProjectLink};
+
+#[derive(Debug, thiserror::Error)]
+pub enum MusicError {
+    #[error("Failed to read {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("Failed to parse music entry from {path:?}: {source}")]
+    Metadata {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+}
+
+/// Bandcamp's embedded player, for an album (optionally starting from one of its tracks) or a
+/// single track; IDs are found in the "share / embed" dialog
+#[derive(Deserialize, Debug, Clone)]
+pub struct BandcampEmbed {
+    pub album: Option<u64>,
+    pub track: Option<u64>,
+}
+
+impl BandcampEmbed {
+    pub fn player_url(&self) -> String {
+        let mut url = "https://bandcamp.com/EmbeddedPlayer".to_owned();
+        if let Some(album) = self.album {
+            url.push_str(&format!("/album={}", album));
+        }
+        url.push_str("/size=small/bgcol=ffffff/linkcol=0687f5");
+        if let Some(track) = self.track {
+            url.push_str(&format!("/track={}", track));
+        }
+        url.push_str("/transparent=true/");
+        url
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Release {
+    pub title: String,
+    /// for releases by other artists, e.g. the ones only recorded or mixed; the entry's name
+    /// is used by default
+    pub artist: Option<String>,
+    /// release page, used as the player's fallback link
+    pub url: String,
+    pub bandcamp: BandcampEmbed,
+    /// shown before the release
+    pub note: Option<Markdown>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Video {
+    pub youtube: String,
+    /// in seconds
+    pub start: Option<u32>,
+    /// shown before the video
+    pub note: Option<Markdown>,
+}
+
+impl Video {
+    pub fn embed_url(&self) -> String {
+        match self.start {
+            Some(start) => format!(
+                "https://www.youtube.com/embed/{}?start={}",
+                self.youtube, start
+            ),
+            None => format!("https://www.youtube.com/embed/{}", self.youtube),
+        }
+    }
+}
+
+/// audio file served from the audio dir
+#[derive(Deserialize, Debug, Clone)]
+pub struct Recording {
+    pub title: String,
+    /// path inside the audio dir
+    pub file: String,
+}
+
+impl Recording {
+    pub fn mime_type(&self) -> &'static str {
+        audio::mime_type(&self.file)
+    }
+
+    pub fn url(&self) -> String {
+        format!("/audio/{}", imaging::escape_url_path(&self.file))
+    }
+}
+
+/// third-party player or anything else not covered by the other sections, as raw HTML
+#[derive(Deserialize, Debug, Clone)]
+pub struct Embed {
+    pub html: String,
+    /// shown before the embed
+    pub note: Option<Markdown>,
+}
+
+/// a band or a solo project, loaded from a single YAML file in the music dir
+#[derive(Deserialize, Debug, Clone)]
+pub struct MusicEntry {
+    pub name: String,
+    /// years active, `end` is unset for ongoing projects
+    pub start: Option<u16>,
+    pub end: Option<u16>,
+    #[serde(default = "Vec::new")]
+    pub links: Vec<ProjectLink>,
+    #[serde(default = "Vec::new")]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub description: Markdown,
+    #[serde(default = "Vec::new")]
+    pub releases: Vec<Release>,
+    #[serde(default = "Vec::new")]
+    pub recordings: Vec<Recording>,
+    #[serde(default = "Vec::new")]
+    pub videos: Vec<Video>,
+    /// videos are collapsed under this summary, if set
+    pub videos_summary: Option<String>,
+    #[serde(default = "Vec::new")]
+    pub embeds: Vec<Embed>,
+    /// embeds are collapsed under this summary, if set
+    pub embeds_summary: Option<String>,
+}
+
+impl MusicEntry {
+    pub fn load(path: &Path) -> Result<MusicEntry, MusicError> {
+        let file = File::open(path).map_err(|source| MusicError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+        serde_yaml::from_reader(file).map_err(|source| MusicError::Metadata {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    /// e.g. "2016 - pres."
+    pub fn years(&self) -> Option<String> {
+        self.start.map(|start| match self.end {
+            Some(end) => format!("{} - {}", start, end),
+            None => format!("{} - pres.", start),
+        })
+    }
+}
+
+/// YAML files in the music dir, one per entry
+pub fn is_music_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|ext| ext == "yaml" || ext == "yml")
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MusicCatalog {
+    /// in the order of the filenames, which may be prefixed with numbers for that
+    pub entries: Vec<MusicEntry>,
+    /// files that failed to load
+    pub failed: Vec<LoadFailure>,
+}
+
+impl std::fmt::Display for MusicCatalog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "MusicCatalog {{ {} entries, {} failed }}",
+            self.entries.len(),
+            self.failed.len()
+        )
+    }
+}
+
+impl MusicCatalog {
+    pub fn load(music_dir: &Path) -> Result<MusicCatalog, MusicError> {
+        tracing::info!("Loading music catalog from {:?}", music_dir);
+        let mut paths: Vec<PathBuf> = music_dir
+            .read_dir()
+            .map_err(|source| MusicError::Io {
+                path: music_dir.to_owned(),
+                source,
+            })?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| is_music_file(path))
+            .collect();
+        paths.sort();
+
+        let mut catalog = MusicCatalog::default();
+        for path in paths {
+            match MusicEntry::load(&path) {
+                Ok(entry) => catalog.entries.push(entry),
+                Err(e) => {
+                    tracing::warn!("Failed to load music entry from {:?}: {}", path, e);
+                    catalog.failed.push(LoadFailure {
+                        path,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(catalog)
+    }
+}
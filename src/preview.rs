@@ -0,0 +1,94 @@
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use constant_time_eq::constant_time_eq;
+use jiff::Timestamp;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use std::collections::HashMap;
+use std::path::Component;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+use crate::errorpage::ErrorPage;
+use crate::AppState;
+
+/// carries the preview token to the media of a previewed project, which the page links to
+/// without it
+const COOKIE: &str = "preview";
+
+/// whether `token` is the preview token; compared in constant time, so that it can't be guessed
+/// by timing the responses
+pub fn is_valid(state: &AppState, token: &str) -> bool {
+    state
+        .preview_token
+        .as_ref()
+        .is_some_and(|expected| constant_time_eq(expected.as_bytes(), token.as_bytes()))
+}
+
+/// `Set-Cookie` value letting the browser load the media of the previewed project
+pub fn media_cookie(token: &str, slug: &str) -> Option<HeaderValue> {
+    let token = utf8_percent_encode(token, NON_ALPHANUMERIC);
+    HeaderValue::from_str(&format!(
+        "{}={}; Path=/projects/media/{}/; HttpOnly; SameSite=Lax",
+        COOKIE, token, slug
+    ))
+    .ok()
+}
+
+fn cookie_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE)
+        .map(|(_, token)| percent_decode_str(token).decode_utf8_lossy().into_owned())
+}
+
+/// project media files; those of unpublished projects need the preview token, either in the
+/// query or in the cookie set by the preview page
+pub async fn project_media(
+    State(state): State<AppState>,
+    Path((slug, file)): Path<(String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request,
+) -> Result<Response, ErrorPage> {
+    let not_found = || ErrorPage::not_found("there is no such media file");
+    let is_safe = std::path::Path::new(&file)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    if !is_safe {
+        return Err(not_found());
+    }
+    let (media_path, is_draft) = {
+        let catalog = state.project_catalog.load();
+        let project = catalog.find(&slug).ok_or_else(not_found)?;
+        let is_draft = !project.is_published(Timestamp::now());
+        if is_draft {
+            let token = params
+                .get("preview")
+                .cloned()
+                .or_else(|| cookie_token(request.headers()));
+            if !token.is_some_and(|token| is_valid(&state, &token)) {
+                return Err(not_found());
+            }
+        }
+        (project.media_dir.join(&file), is_draft)
+    };
+    let mut response = ServeFile::new(media_path)
+        .oneshot(request)
+        .await
+        .map_err(|e| match e {})?
+        .into_response();
+    if is_draft {
+        // not for shared caches
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("private, no-cache"),
+        );
+    }
+    Ok(response)
+}
@@ -1,47 +1,230 @@
 #![allow(unreachable_patterns)]
+use comrak::nodes::{AstNode, NodeValue};
 use fancy_regex::Regex;
 use itertools::Itertools;
+use jiff::Timestamp;
 use std::cmp::Reverse;
-use std::{fs::File, io, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use crate::date::Date;
-use serde::Deserialize;
+use crate::health::LoadFailure;
+use crate::imaging;
+use crate::projectmedia::{self, MediaError, MediaImage};
+use crate::search::SearchIndex;
+use crate::socialcard::{self, SocialImage};
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ProjectLink {
     pub name: String,
     pub url: String,
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ProjectTag {
     pub category: String,
     pub name: String,
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum ProjectError {
+    #[error("{path:?} must be a directory")]
+    NotADirectory { path: PathBuf },
+    #[error("Failed to read {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("Invalid project metadata in {path:?}: {source}")]
+    Metadata {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    #[error("Invalid tag: {tag:?}")]
+    InvalidTag { tag: String },
+    #[error("Project slug {slug:?} must consist of latin letters, digits, dashes and underscores")]
+    InvalidSlug { slug: String },
+    #[error("Failed to render Markdown from {path:?}: {source}")]
+    Render {
+        path: PathBuf,
+        source: std::fmt::Error,
+    },
+    #[error(transparent)]
+    Media(#[from] MediaError),
+    #[error("Failed to read cover image {path:?}: {source}")]
+    Cover {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    #[error("Failed to render social preview image for {path:?}: {source}")]
+    SocialCard {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    #[error("Project catalog contains duplicate slug or alias {slug:?}")]
+    DuplicateSlug { slug: String },
+}
+
+impl ProjectError {
+    fn io(path: &Path) -> impl FnOnce(io::Error) -> ProjectError + '_ {
+        |source| ProjectError::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+}
+
 impl std::fmt::Display for ProjectTag {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("{}:{}", self.category, self.name))
     }
 }
 
+/// slugs name directories and URL path segments, so they are kept to a safe subset of ASCII
+pub fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
 impl ProjectTag {
-    pub fn parse(s: &str) -> io::Result<ProjectTag> {
+    pub fn parse(s: &str) -> Result<ProjectTag, ProjectError> {
         if let Some((category, body)) = s.split_once(":") {
             Ok(ProjectTag {
                 category: category.to_owned(),
                 name: body.to_owned(),
             })
         } else {
-            Err(io::Error::other(format!("invalid tag: {:?}", s)))
+            Err(ProjectError::InvalidTag { tag: s.to_owned() })
+        }
+    }
+}
+
+/// a set of tag conditions, as given in the URL query: all of the `tag` params must be present,
+/// at least one of the `any` params must be present (if there are any) and tags prefixed with
+/// `-` must be absent, e.g. `?tag=code:rust&any=platform:web&any=platform:terminal&tag=-topic:ai`
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagFilter {
+    pub all: Vec<ProjectTag>,
+    pub any: Vec<ProjectTag>,
+    pub none: Vec<ProjectTag>,
+}
+
+impl TagFilter {
+    pub fn from_query(params: &[(String, String)]) -> Result<TagFilter, ProjectError> {
+        let mut filter = TagFilter::default();
+        for (key, value) in params {
+            if key != "tag" && key != "any" {
+                continue;
+            }
+            let (negated, tag) = match value.strip_prefix('-') {
+                Some(tag) => (true, ProjectTag::parse(tag)?),
+                None => (false, ProjectTag::parse(value)?),
+            };
+            let group = match (key.as_str(), negated) {
+                (_, true) => &mut filter.none,
+                ("tag", _) => &mut filter.all,
+                _ => &mut filter.any,
+            };
+            if !group.contains(&tag) {
+                group.push(tag);
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn matches(&self, project: &Project) -> bool {
+        let tags = &project.metadata.tags;
+        self.all.iter().all(|t| tags.contains(t))
+            && (self.any.is_empty() || self.any.iter().any(|t| tags.contains(t)))
+            && !self.none.iter().any(|t| tags.contains(t))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.all.is_empty() && self.any.is_empty() && self.none.is_empty()
+    }
+
+    pub fn contains(&self, tag: &ProjectTag) -> bool {
+        self.all.contains(tag) || self.any.contains(tag) || self.none.contains(tag)
+    }
+
+    /// the simplest filter by a single tag, for which we have dedicated pages and feeds
+    pub fn single_tag(&self) -> Option<&ProjectTag> {
+        match (
+            self.all.as_slice(),
+            self.any.is_empty(),
+            self.none.is_empty(),
+        ) {
+            ([tag], true, true) => Some(tag),
+            _ => None,
+        }
+    }
+
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        self.all
+            .iter()
+            .map(|t| ("tag", t.to_string()))
+            .chain(self.any.iter().map(|t| ("any", t.to_string())))
+            .chain(self.none.iter().map(|t| ("tag", format!("-{}", t))))
+            .collect()
+    }
+
+    /// URL query string representing the filter, without the leading `?`
+    pub fn query(&self) -> String {
+        form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(self.params())
+            .finish()
+    }
+
+    pub fn with_all(&self, tag: &ProjectTag) -> TagFilter {
+        let mut filter = self.without(tag);
+        filter.all.push(tag.clone());
+        filter
+    }
+
+    pub fn with_any(&self, tag: &ProjectTag) -> TagFilter {
+        let mut filter = self.without(tag);
+        filter.any.push(tag.clone());
+        filter
+    }
+
+    pub fn with_none(&self, tag: &ProjectTag) -> TagFilter {
+        let mut filter = self.without(tag);
+        filter.none.push(tag.clone());
+        filter
+    }
+
+    pub fn without(&self, tag: &ProjectTag) -> TagFilter {
+        TagFilter {
+            all: self.all.iter().filter(|&t| t != tag).cloned().collect(),
+            any: self.any.iter().filter(|&t| t != tag).cloned().collect(),
+            none: self.none.iter().filter(|&t| t != tag).cloned().collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for TagFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts: Vec<String> = self.all.iter().map(|t| t.to_string()).collect();
+        if !self.any.is_empty() {
+            parts.push(format!("({})", self.any.iter().join(" or ")));
         }
+        parts.extend(self.none.iter().map(|t| format!("not {}", t)));
+        f.write_str(&parts.join(" and "))
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ProjectMetadata {
     pub title: String,
     pub slug: String,
+    /// former slugs, redirecting to the current one
+    #[serde(default = "Vec::new")]
+    pub aliases: Vec<String>,
 
     #[serde(default = "Vec::new")]
     pub links: Vec<ProjectLink>,
@@ -59,10 +242,30 @@ pub struct ProjectMetadata {
     #[allow(dead_code)]
     pub end: Option<Date>,
 
-    #[serde(default = "Vec::new", alias = "tags")]
+    #[serde(default = "Vec::new", alias = "tags", skip_serializing)]
     tags_raw: Vec<String>,
     #[serde(default = "Vec::new")]
     pub tags: Vec<ProjectTag>,
+
+    /// drafts are only accessible with the preview token
+    #[serde(default)]
+    pub draft: bool,
+    /// projects scheduled for the future are treated as drafts until then,
+    /// e.g. `publish_at: 2025-01-01T12:00:00+03:00`
+    pub publish_at: Option<Timestamp>,
+
+    /// short description for link previews, the first paragraph of the body by default
+    pub summary: Option<String>,
+    /// image for link previews, e.g. `media/screenshot.png`; a card with the title is generated
+    /// for projects without one
+    pub cover: Option<String>,
+}
+
+impl ProjectMetadata {
+    /// tags as written in `meta.yaml`, before parsing
+    pub fn raw_tags(&self) -> &[String] {
+        &self.tags_raw
+    }
 }
 
 fn default_math() -> bool {
@@ -74,6 +277,16 @@ pub struct Project {
     pub metadata: ProjectMetadata,
     body_md: String,
     pub body_html: String,
+    /// project's source directory
+    pub dir: PathBuf,
+    /// project's subdir of the shared media dir, with media files linked from the project's dir
+    /// along with the optimized versions of images
+    pub media_dir: PathBuf,
+    /// summary or the beginning of the body, as plain text
+    pub description: String,
+    pub social_image: SocialImage,
+    /// generated social preview image, PNG-encoded; `None` for projects with a cover
+    pub social_card: Option<Arc<[u8]>>,
 }
 
 impl std::fmt::Debug for Project {
@@ -86,17 +299,208 @@ impl std::fmt::Debug for Project {
     }
 }
 
+pub fn markdown_options() -> comrak::Options<'static> {
+    let mut options = comrak::Options::default();
+    options.render.r#unsafe = true;
+    options.parse.smart = true;
+    options.extension.strikethrough = true;
+    options.extension.footnotes = true;
+    options.extension.inline_footnotes = true;
+    options
+}
+
+/// Markdown text from YAML, rendered into HTML on loading; like project bodies, it is trusted
+/// content, so raw HTML is allowed
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(from = "String")]
+pub struct Markdown {
+    pub html: String,
+}
+
+impl From<String> for Markdown {
+    fn from(text: String) -> Self {
+        Markdown {
+            html: comrak::markdown_to_html(&text, &markdown_options()),
+        }
+    }
+}
+
+/// renders Markdown into HTML, rewriting references to the project's media dir (`media/...`)
+/// into URLs under `media_url_prefix` and replacing images with their optimized versions
+fn render_markdown(
+    body_md: &str,
+    media_url_prefix: &str,
+    media_images: &HashMap<String, MediaImage>,
+) -> Result<String, std::fmt::Error> {
+    let arena = comrak::Arena::new();
+    let options = markdown_options();
+    let root = comrak::parse_document(&arena, body_md, &options);
+    let html_media_re = Regex::new("(src|href)=\"media/").unwrap();
+    let html_media_replacement = format!("$1=\"{}", media_url_prefix);
+    // the tree is modified in the loop, so it can't be traversed lazily
+    let nodes: Vec<&AstNode> = root.descendants().collect();
+    for node in nodes {
+        // image's children are its alt text, which must be read before borrowing the node mutably
+        let alt = matches!(node.data().value, NodeValue::Image(_)).then(|| plain_text(node));
+        let picture_html = match &mut node.data_mut().value {
+            NodeValue::Image(link) => match link.url.strip_prefix("media/") {
+                Some(filename) => match media_images.get(filename) {
+                    Some(image) => Some(image.html(
+                        media_url_prefix,
+                        alt.as_deref().unwrap_or_default(),
+                        &link.title,
+                    )),
+                    None => {
+                        link.url = format!("{}{}", media_url_prefix, filename);
+                        None
+                    }
+                },
+                None => None,
+            },
+            NodeValue::Link(link) => {
+                if let Some(filename) = link.url.strip_prefix("media/") {
+                    link.url = format!("{}{}", media_url_prefix, filename);
+                }
+                None
+            }
+            // raw HTML is used for videos, embedded PDFs and such
+            NodeValue::HtmlInline(html) => {
+                *html = html_media_re
+                    .replace_all(html, &html_media_replacement)
+                    .into_owned();
+                None
+            }
+            NodeValue::HtmlBlock(block) => {
+                block.literal = html_media_re
+                    .replace_all(&block.literal, &html_media_replacement)
+                    .into_owned();
+                None
+            }
+            _ => None,
+        };
+        if let Some(picture_html) = picture_html {
+            // alt text is now a part of the raw HTML
+            while let Some(child) = node.first_child() {
+                child.detach();
+            }
+            node.data_mut().value = NodeValue::Raw(picture_html);
+        }
+    }
+    let mut html = String::new();
+    comrak::format_html(root, &options, &mut html)?;
+    Ok(html)
+}
+
+/// descriptions longer than that are truncated
+const DESCRIPTION_MAX_CHARS: usize = 200;
+
+/// plain text of the first paragraph, truncated to a sentence or two
+fn summarize(body_md: &str) -> String {
+    let arena = comrak::Arena::new();
+    let root = comrak::parse_document(&arena, body_md, &markdown_options());
+    let Some(paragraph) = root
+        .children()
+        .find(|n| matches!(n.data().value, NodeValue::Paragraph))
+    else {
+        return String::new();
+    };
+    let text: String = paragraph
+        .descendants()
+        .filter_map(|n| match &n.data().value {
+            NodeValue::Text(text) => Some(text.to_string()),
+            NodeValue::Code(code) => Some(code.literal.clone()),
+            NodeValue::SoftBreak | NodeValue::LineBreak => Some(" ".to_owned()),
+            _ => None,
+        })
+        .collect();
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut summary = String::new();
+    for word in words.iter() {
+        if summary.chars().count() + word.chars().count() + 1 > DESCRIPTION_MAX_CHARS {
+            summary.push('…');
+            break;
+        }
+        if !summary.is_empty() {
+            summary.push(' ');
+        }
+        summary.push_str(word);
+    }
+    summary
+}
+
+/// cover image's display version and its size; `original_size` is used for images served as-is
+fn cover_image(
+    filename: &str,
+    original_size: (u32, u32),
+    media_url_prefix: &str,
+    media_images: &HashMap<String, MediaImage>,
+) -> SocialImage {
+    if let Some(image) = media_images.get(filename) {
+        return SocialImage {
+            url: format!(
+                "{}{}",
+                media_url_prefix,
+                imaging::escape_url_path(&image.display)
+            ),
+            width: image.width,
+            height: image.height,
+        };
+    }
+    // not processed, e.g. GIFs
+    let (width, height) = original_size;
+    SocialImage {
+        url: format!("{}{}", media_url_prefix, imaging::escape_url_path(filename)),
+        width,
+        height,
+    }
+}
+
+fn plain_text<'a>(node: &'a AstNode<'a>) -> String {
+    node.descendants()
+        .filter_map(|n| match &n.data().value {
+            NodeValue::Text(text) => Some(text.to_string()),
+            NodeValue::Code(code) => Some(code.literal.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
 impl Project {
-    pub fn load(dir: &Path, project_media_dir: &Path) -> io::Result<Project> {
+    /// `taken` are the slugs and aliases of the other projects in the catalog
+    pub fn load(
+        dir: &Path,
+        project_media_dir: &Path,
+        taken: &HashSet<String>,
+    ) -> Result<Project, ProjectError> {
         tracing::info!("Loading project from {:?}", dir);
         if !dir.is_dir() {
-            return Err(io::Error::other("path must be a directory".to_owned()));
+            return Err(ProjectError::NotADirectory {
+                path: dir.to_owned(),
+            });
         }
 
         // loading metadata
+        let meta_path = dir.join("meta.yaml");
         let mut metadata: ProjectMetadata =
-            serde_yaml::from_reader(File::open(dir.join("meta.yaml"))?)
-                .map_err(|e| io::Error::other(e.to_string()))?;
+            serde_yaml::from_reader(File::open(&meta_path).map_err(ProjectError::io(&meta_path))?)
+                .map_err(|source| ProjectError::Metadata {
+                    path: meta_path.clone(),
+                    source,
+                })?;
+        // the slug names the media dir synced (and wiped) below, so it must stay inside the
+        // media dir and not belong to another project; aliases are checked here as well, so that
+        // nothing is synced for a project the catalog would reject anyway
+        if !is_valid_slug(&metadata.slug) {
+            return Err(ProjectError::InvalidSlug {
+                slug: metadata.slug,
+            });
+        }
+        let mut names: HashSet<&String> = HashSet::new();
+        for name in std::iter::once(&metadata.slug).chain(metadata.aliases.iter()) {
+            if taken.contains(name) || !names.insert(name) {
+                return Err(ProjectError::DuplicateSlug { slug: name.clone() });
+            }
+        }
         if let Some(ref github_link_url) = metadata.github {
             metadata.links.insert(
                 0,
@@ -108,20 +512,63 @@ impl Project {
         }
         // post-parsing tags
         for tag_raw in metadata.tags_raw.iter() {
-            metadata.tags.push(ProjectTag::parse(&tag_raw)?);
+            metadata.tags.push(ProjectTag::parse(tag_raw)?);
         }
 
-        // loading project description body
-        let body_md = std::io::read_to_string(File::open(dir.join("body.md"))?)?;
+        // everything that may fail goes before the media is synced, so that nothing is published
+        // for a project that fails to load
+        let body_path = dir.join("body.md");
+        let body_md = std::fs::read_to_string(&body_path).map_err(ProjectError::io(&body_path))?;
         // preprocessing Markdown: insert nicer typography
         // body_md = body_md.replace("---", "—");
-        let mut options = comrak::Options::default();
-        options.render.r#unsafe = true;
-        options.parse.smart = true;
-        options.extension.strikethrough = true;
-        options.extension.footnotes = true;
-        options.extension.inline_footnotes = true;
-        let mut body_html = comrak::markdown_to_html(&body_md, &options);
+        let description = match &metadata.summary {
+            Some(summary) => summary.clone(),
+            None => summarize(&body_md),
+        };
+        let source_media_dir = dir.join("media");
+        let cover = match &metadata.cover {
+            Some(cover) => {
+                let filename = cover.strip_prefix("media/").unwrap_or(cover);
+                let path = source_media_dir.join(filename);
+                let size = image::image_dimensions(&path)
+                    .map_err(|source| ProjectError::Cover { path, source })?;
+                Some((filename, size))
+            }
+            None => None,
+        };
+        let social_card = match cover {
+            Some(_) => None,
+            None => {
+                let card = socialcard::render(&metadata.title).map_err(|source| {
+                    ProjectError::SocialCard {
+                        path: dir.to_owned(),
+                        source,
+                    }
+                })?;
+                Some(Arc::from(card))
+            }
+        };
+
+        // syncing media into the project's own subdir of the media dir, optimizing images on
+        // the way
+        let media_dir = project_media_dir.join(&metadata.slug);
+        let media_images = if source_media_dir.is_dir() {
+            projectmedia::sync(&source_media_dir, &media_dir)?
+        } else {
+            if media_dir.exists() {
+                std::fs::remove_dir_all(&media_dir).map_err(ProjectError::io(&media_dir))?;
+            }
+            HashMap::new()
+        };
+
+        let media_url_prefix = format!("/projects/media/{}/", metadata.slug);
+        let mut body_html =
+            render_markdown(&body_md, &media_url_prefix, &media_images).map_err(|source| {
+                ProjectError::Render {
+                    path: body_path.clone(),
+                    source,
+                }
+            })?;
         // posprocessing HTML with regex, yes I know I know
         // make all anchors target a blank page, except those linking to hash on the current page (e.g. footnotes)
         let anchor_re = Regex::new("<a\\s+href=\"(?!#)").unwrap();
@@ -129,46 +576,85 @@ impl Project {
             .replace_all(&body_html, "<a target=\"_blank\" href=\"")
             .to_string();
 
-        // copying media to a dedicated dir
-        let media_dir = dir.join("media");
-        if media_dir.exists() && media_dir.is_dir() {
-            for file in media_dir.read_dir()? {
-                if let Ok(file) = file {
-                    if let Some(first_char) = file.file_name().to_string_lossy().get(0..1) {
-                        if first_char == "." {
-                            continue;
-                        }
-                    }
-                    let target_file = project_media_dir.join(file.file_name());
-                    if target_file.exists() {
-                        return Err(io::Error::other(format!("Project media {:?} name is duplicated, conflicting with an already loaded project", file.path())));
-                    }
-                    std::fs::copy(file.path(), &target_file)?;
-                }
-            }
-        }
+        // link previews
+        let social_image = match cover {
+            Some((filename, size)) => cover_image(filename, size, &media_url_prefix, &media_images),
+            None => SocialImage {
+                url: format!("/projects/cards/{}.png", metadata.slug),
+                width: socialcard::WIDTH,
+                height: socialcard::HEIGHT,
+            },
+        };
+
         Ok(Project {
             metadata,
             body_md,
             body_html,
+            dir: dir.to_owned(),
+            media_dir,
+            description,
+            social_image,
+            social_card,
         })
     }
+
+    /// the slug and the aliases, any of them identifies the project
+    pub fn slugs(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.metadata.slug).chain(self.metadata.aliases.iter())
+    }
+
+    pub fn is_published(&self, now: Timestamp) -> bool {
+        !self.metadata.draft && self.metadata.publish_at.is_none_or(|t| t <= now)
+    }
+
+    pub fn body_md(&self) -> &str {
+        &self.body_md
+    }
+
+    /// removes media files synced by `Project::load`
+    pub fn remove_media(&self) {
+        if !self.media_dir.exists() {
+            return;
+        }
+        if let Err(e) = std::fs::remove_dir_all(&self.media_dir) {
+            tracing::warn!(
+                "Error deleting project media dir {:?}: {}",
+                self.media_dir,
+                e
+            );
+        }
+    }
+}
+
+/// project directories are all subdirectories of the catalog dir, except for the template
+pub fn is_project_dir(path: &Path) -> bool {
+    path.is_dir() && path.file_name().is_some_and(|name| name != "template")
 }
 
 pub type TagGroups = Vec<(String, Vec<ProjectTag>)>;
 
+/// like `TagGroups`, but with the number of projects having each tag
+pub type TagCounts = Vec<(String, Vec<(ProjectTag, usize)>)>;
+
 #[derive(Debug, Clone)]
 pub struct ProjectCatalog {
+    /// all the loaded projects, including unpublished ones
     pub projects: Vec<Project>,
-    pub tag_groups: TagGroups,
+    pub search_index: SearchIndex,
+    /// project dirs that failed to load
+    pub failed: Vec<LoadFailure>,
+    /// project aliases, mapped to the current slugs
+    pub redirects: HashMap<String, String>,
 }
 
 impl std::fmt::Display for ProjectCatalog {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&format!(
-            "ProjectCatalog {{ {} projects, tags: {} }}",
+            "ProjectCatalog {{ {} projects ({} published, {} failed), tags: {} }}",
             self.projects.len(),
-            self.tag_groups
+            self.published().count(),
+            self.failed.len(),
+            self.tag_groups()
                 .iter()
                 .map(|(category, tags)| format!(
                     "{}:[{}]",
@@ -181,48 +667,87 @@ impl std::fmt::Display for ProjectCatalog {
 }
 
 impl ProjectCatalog {
-    pub fn load(projects_dir: &Path, project_media_dir: &Path) -> io::Result<ProjectCatalog> {
+    pub fn load(
+        projects_dir: &Path,
+        project_media_dir: &Path,
+    ) -> Result<ProjectCatalog, ProjectError> {
         tracing::info!(
-            "Loading project catalog from {:?}, copying media into {:?}",
+            "Loading project catalog from {:?}, syncing media into {:?}",
             projects_dir,
             project_media_dir
         );
-        let mut projects: Vec<Project> = projects_dir
-            .read_dir()?
-            .filter_map(|maybe_dir_entry| {
-                if let Ok(entry) = maybe_dir_entry {
-                    if entry.file_name() == "template" || !entry.path().is_dir() {
-                        return None;
-                    }
-                    let maybe_project = Project::load(&entry.path(), project_media_dir);
-                    if let Ok(project) = maybe_project {
-                        return Some(project);
-                    } else {
-                        tracing::warn!(
-                            "Failed to load project from {:?}: {:?}",
-                            entry.path(),
-                            maybe_project
-                        );
-                    }
+        let mut projects: Vec<Project> = Vec::new();
+        let mut failed: Vec<LoadFailure> = Vec::new();
+        let mut taken: HashSet<String> = HashSet::new();
+        for entry in projects_dir
+            .read_dir()
+            .map_err(ProjectError::io(projects_dir))?
+            .flatten()
+        {
+            let path = entry.path();
+            if !is_project_dir(&path) {
+                continue;
+            }
+            match Project::load(&path, project_media_dir, &taken) {
+                Ok(project) => {
+                    taken.extend(project.slugs().cloned());
+                    projects.push(project);
                 }
-                None
-            })
-            .collect();
+                Err(e) => {
+                    tracing::warn!("Failed to load project from {:?}: {}", path, e);
+                    failed.push(LoadFailure {
+                        path,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+
+        let mut catalog = ProjectCatalog::from_projects(projects)?;
+        catalog.failed = failed;
+        Ok(catalog)
+    }
 
+    pub fn from_projects(mut projects: Vec<Project>) -> Result<ProjectCatalog, ProjectError> {
         // sorting by date newest->oldest
         projects.sort_by(|a, b| b.metadata.start.cmp(&a.metadata.start));
 
-        // validating slug uniqueness
-        let mut slugs: Vec<String> = projects.iter().map(|p| p.metadata.slug.clone()).collect();
-        slugs.sort();
-        slugs.dedup();
-        if slugs.len() != projects.len() {
-            return Err(io::Error::other("Project catalog contains duplicate slugs"));
+        // validating uniqueness of slugs and aliases, any of them identifies a single project
+        let mut slugs: HashSet<&str> = HashSet::new();
+        let mut redirects: HashMap<String, String> = HashMap::new();
+        for project in projects.iter() {
+            let metadata = &project.metadata;
+            for slug in project.slugs() {
+                if !slugs.insert(slug) {
+                    return Err(ProjectError::DuplicateSlug { slug: slug.clone() });
+                }
+            }
+            for alias in metadata.aliases.iter() {
+                redirects.insert(alias.clone(), metadata.slug.clone());
+            }
         }
 
-        // tag groups
-        let mut tags: Vec<ProjectTag> = projects
-            .iter()
+        let search_index = SearchIndex::build(&projects);
+
+        Ok(ProjectCatalog {
+            projects,
+            search_index,
+            failed: Vec::new(),
+            redirects,
+        })
+    }
+
+    /// projects visible to everyone at the moment; scheduled ones show up here without reloading
+    pub fn published(&self) -> impl Iterator<Item = &Project> {
+        let now = Timestamp::now();
+        self.projects.iter().filter(move |p| p.is_published(now))
+    }
+
+    /// tags of the published projects, grouped by category; categories and tags within them
+    /// are sorted by popularity
+    pub fn tag_groups(&self) -> TagGroups {
+        let mut tags: Vec<ProjectTag> = self
+            .published()
             .flat_map(|p| p.metadata.tags.clone())
             .collect();
         tags.sort_by_key(|t| t.category.clone());
@@ -243,14 +768,209 @@ impl ProjectCatalog {
             })
             .collect_vec();
         tag_groups.sort_by_key(|(_, group)| Reverse(group.len()));
+        tag_groups
+    }
 
-        Ok(ProjectCatalog {
-            projects,
-            tag_groups,
-        })
+    /// returns a new catalog with a single project (re)loaded from `dir`; if the directory
+    /// was removed or the project fails to load, it is dropped from the catalog
+    pub fn reload_project(
+        &self,
+        dir: &Path,
+        project_media_dir: &Path,
+    ) -> Result<ProjectCatalog, ProjectError> {
+        let mut projects: Vec<Project> = Vec::with_capacity(self.projects.len());
+        let mut previous: Option<&Project> = None;
+        for project in self.projects.iter() {
+            if project.dir == dir {
+                previous = Some(project);
+            } else {
+                projects.push(project.clone());
+            }
+        }
+        let mut failed: Vec<LoadFailure> = self
+            .failed
+            .iter()
+            .filter(|f| f.path != dir)
+            .cloned()
+            .collect();
+        let mut reloaded: Option<Project> = None;
+        if is_project_dir(dir) {
+            let taken: HashSet<String> = projects.iter().flat_map(|p| p.slugs().cloned()).collect();
+            match Project::load(dir, project_media_dir, &taken) {
+                Ok(project) => reloaded = Some(project),
+                Err(e) => {
+                    tracing::warn!("Failed to reload project from {:?}: {}", dir, e);
+                    failed.push(LoadFailure {
+                        path: dir.to_owned(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        // media is synced incrementally, so it's only removed when it's not needed anymore, and
+        // not before the new catalog turns out valid, since the current one stays otherwise
+        let stale_media = previous.filter(|previous| {
+            reloaded
+                .as_ref()
+                .is_none_or(|p| p.media_dir != previous.media_dir)
+        });
+        projects.extend(reloaded);
+        let mut catalog = ProjectCatalog::from_projects(projects)?;
+        if let Some(previous) = stale_media {
+            previous.remove_media();
+        }
+        catalog.failed = failed;
+        Ok(catalog)
+    }
+
+    /// removes media dirs of projects missing from the catalog, e.g. left after renaming
+    pub fn remove_stale_media(&self, project_media_dir: &Path) -> io::Result<()> {
+        let media_dirs: HashSet<&Path> = self
+            .projects
+            .iter()
+            .map(|p| p.media_dir.as_path())
+            .collect();
+        for entry in project_media_dir.read_dir()?.flatten() {
+            let path = entry.path();
+            if media_dirs.contains(path.as_path()) {
+                continue;
+            }
+            tracing::info!("Removing stale project media {:?}", path);
+            if path.is_dir() {
+                std::fs::remove_dir_all(&path)?;
+            } else {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// counts matching projects for each tag not yet in the filter, skipping tags with no matches
+    pub fn tag_counts(&self, filter: &TagFilter) -> TagCounts {
+        let matching: Vec<&Project> = self.published().filter(|p| filter.matches(p)).collect();
+        self.tag_groups()
+            .into_iter()
+            .map(|(category, tags)| {
+                (
+                    category,
+                    tags.iter()
+                        .filter(|t| !filter.contains(t))
+                        .map(|t| {
+                            let count = matching
+                                .iter()
+                                .filter(|p| p.metadata.tags.contains(t))
+                                .count();
+                            (t.clone(), count)
+                        })
+                        .filter(|(_, count)| *count > 0)
+                        .collect_vec(),
+                )
+            })
+            .filter(|(_, tags)| !tags.is_empty())
+            .collect()
     }
 
+    /// looks up a project by slug, including unpublished ones
     pub fn find<'a>(&'a self, slug: &str) -> Option<&'a Project> {
         self.projects.iter().find(|&p| p.metadata.slug == slug)
     }
+
+    pub fn find_published<'a>(&'a self, slug: &str) -> Option<&'a Project> {
+        self.published().find(|&p| p.metadata.slug == slug)
+    }
+
+    /// looks up a project by one of its former slugs, including unpublished ones
+    pub fn find_by_alias<'a>(&'a self, alias: &str) -> Option<&'a Project> {
+        self.redirects.get(alias).and_then(|slug| self.find(slug))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn tag(s: &str) -> ProjectTag {
+        ProjectTag::parse(s).unwrap()
+    }
+
+    #[test]
+    fn tag_filter_from_query() {
+        let filter = TagFilter::from_query(&params(&[
+            ("tag", "code:rust"),
+            ("any", "platform:web"),
+            ("any", "platform:terminal"),
+            ("tag", "-topic:ai"),
+            ("any", "-topic:crypto"),
+            ("q", "ignored"),
+        ]))
+        .unwrap();
+        assert_eq!(filter.all, vec![tag("code:rust")]);
+        assert_eq!(
+            filter.any,
+            vec![tag("platform:web"), tag("platform:terminal")]
+        );
+        assert_eq!(filter.none, vec![tag("topic:ai"), tag("topic:crypto")]);
+    }
+
+    #[test]
+    fn tag_filter_from_query_deduplicates() {
+        let filter =
+            TagFilter::from_query(&params(&[("tag", "code:rust"), ("tag", "code:rust")])).unwrap();
+        assert_eq!(filter.all, vec![tag("code:rust")]);
+        assert_eq!(filter.single_tag(), Some(&tag("code:rust")));
+    }
+
+    #[test]
+    fn tag_filter_from_query_rejects_tags_without_category() {
+        let error = TagFilter::from_query(&params(&[("tag", "-rust")])).unwrap_err();
+        assert!(matches!(error, ProjectError::InvalidTag { tag } if tag == "rust"));
+    }
+
+    #[test]
+    fn tag_filter_query_roundtrip() {
+        let filter = TagFilter::default()
+            .with_all(&tag("code:c++"))
+            .with_any(&tag("topic:a & b"));
+        let parsed: Vec<(String, String)> = form_urlencoded::parse(filter.query().as_bytes())
+            .into_owned()
+            .collect();
+        assert_eq!(TagFilter::from_query(&parsed).unwrap(), filter);
+    }
+
+    #[test]
+    fn failed_load_publishes_no_media() {
+        let root = tempfile::tempdir().unwrap();
+        let project_dir = root.path().join("project");
+        let media_dir = root.path().join("project-media");
+        std::fs::create_dir_all(project_dir.join("media")).unwrap();
+        std::fs::write(project_dir.join("media/notes.txt"), "notes").unwrap();
+        std::fs::write(
+            project_dir.join("meta.yaml"),
+            "slug: project\ntitle: Project\nstart:\n  year: 2024\ntags: []\n",
+        )
+        .unwrap();
+
+        // no body.md
+        let result = Project::load(&project_dir, &media_dir, &HashSet::new());
+        assert!(matches!(result, Err(ProjectError::Io { .. })));
+        assert!(!media_dir.join("project").exists());
+
+        // a cover that doesn't exist
+        std::fs::write(project_dir.join("body.md"), "body").unwrap();
+        std::fs::write(
+            project_dir.join("meta.yaml"),
+            "slug: project\ntitle: Project\nstart:\n  year: 2024\ntags: []\ncover: media/missing.png\n",
+        )
+        .unwrap();
+        let result = Project::load(&project_dir, &media_dir, &HashSet::new());
+        assert!(matches!(result, Err(ProjectError::Cover { .. })));
+        assert!(!media_dir.join("project").exists());
+    }
 }
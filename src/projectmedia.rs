@@ -0,0 +1,254 @@
+use image::{imageops::FilterType, GenericImageView};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::html::escape;
+use crate::imaging::{self, ImagingError, VariantFormat};
+
+/// wider images are downscaled for display, project pages are never that wide anyway
+const DISPLAY_MAX_WIDTH: u32 = 1200;
+const WEBP_QUALITY: u8 = 80;
+
+/// formats worth processing; everything else (videos, PDFs, animated GIFs, etc) is served as-is
+const PROCESSED_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum MediaError {
+    #[error(transparent)]
+    Imaging(#[from] ImagingError),
+    #[error("Project media {path:?} conflicts with {other:?}, both are served as {name:?}")]
+    Collision {
+        path: PathBuf,
+        other: String,
+        name: String,
+    },
+}
+
+/// optimized versions of a project media image, as filenames in the project media dir
+#[derive(Debug, Clone)]
+pub struct MediaImage {
+    pub display: String,
+    pub webp: String,
+    /// display version size
+    pub width: u32,
+    pub height: u32,
+}
+
+impl MediaImage {
+    /// `<picture>` markup for the image, with filenames prefixed by `url_prefix`
+    pub fn html(&self, url_prefix: &str, alt: &str, title: &str) -> String {
+        let title_attr = if title.is_empty() {
+            String::new()
+        } else {
+            format!(" title=\"{}\"", escape(title))
+        };
+        format!(
+            "<picture><source type=\"{}\" srcset=\"{}{}\"><img src=\"{}{}\" alt=\"{}\"{} width=\"{}\" height=\"{}\"></picture>",
+            VariantFormat::Webp.mime_type(),
+            url_prefix,
+            imaging::escape_url_path(&self.webp),
+            url_prefix,
+            imaging::escape_url_path(&self.display),
+            escape(alt),
+            title_attr,
+            self.width,
+            self.height,
+        )
+    }
+}
+
+/// brings `target_dir` in sync with the project's media dir: originals are hard-linked into it
+/// (or copied when that's impossible, e.g. across filesystems) and images get display-sized and
+/// WebP versions, regenerated only when the originals change; everything else in `target_dir`
+/// is removed
+pub fn sync(
+    source_dir: &Path,
+    target_dir: &Path,
+) -> Result<HashMap<String, MediaImage>, MediaError> {
+    fs::create_dir_all(target_dir).map_err(ImagingError::io(target_dir))?;
+    let mut images = HashMap::new();
+    let mut expected: HashSet<String> = HashSet::new();
+    // lowercased, as the media may be served from a case-insensitive filesystem
+    let mut produced_by: HashMap<String, String> = HashMap::new();
+    for entry in source_dir
+        .read_dir()
+        .map_err(ImagingError::io(source_dir))?
+        .flatten()
+    {
+        let filename = entry.file_name().to_string_lossy().into_owned();
+        if filename.starts_with('.') {
+            continue;
+        }
+        let source = entry.path();
+        for name in synced_names(&filename) {
+            if let Some(other) = produced_by.insert(name.to_lowercase(), filename.clone()) {
+                return Err(MediaError::Collision {
+                    path: source,
+                    other,
+                    name,
+                });
+            }
+        }
+
+        link(&source, &target_dir.join(&filename))?;
+        expected.insert(filename.clone());
+
+        let Some(names) = DerivativeNames::new(&source) else {
+            continue;
+        };
+        let image = if is_fresh(&target_dir.join(&names.webp), &source) {
+            MediaImage::existing(&filename, &names, target_dir)?
+        } else {
+            tracing::debug!("Processing project media image {:?}", source);
+            process(&source, &filename, &names, target_dir)?
+        };
+        expected.insert(image.display.clone());
+        expected.insert(names.webp);
+        images.insert(filename, image);
+    }
+
+    for entry in target_dir
+        .read_dir()
+        .map_err(ImagingError::io(target_dir))?
+        .flatten()
+    {
+        if !expected.contains(entry.file_name().to_string_lossy().as_ref()) {
+            tracing::debug!("Removing stale project media file {:?}", entry.path());
+            let path = entry.path();
+            fs::remove_file(&path).map_err(ImagingError::io(&path))?;
+        }
+    }
+    Ok(images)
+}
+
+/// names of the files `sync` may write for a media file, including the original's own name
+pub fn synced_names(filename: &str) -> Vec<String> {
+    let mut names = vec![filename.to_owned()];
+    if let Some(derivatives) = DerivativeNames::new(Path::new(filename)) {
+        names.extend([derivatives.display, derivatives.webp]);
+    }
+    names
+}
+
+struct DerivativeNames {
+    display: String,
+    webp: String,
+}
+
+impl DerivativeNames {
+    /// `None` for files that are not processed
+    fn new(file: &Path) -> Option<DerivativeNames> {
+        let extension = file.extension()?.to_string_lossy().to_lowercase();
+        if !PROCESSED_EXTENSIONS.contains(&extension.as_str()) {
+            return None;
+        }
+        // named after the whole filename, so that e.g. `foo.png` and `foo.jpg` don't clash
+        let filename = file.file_name()?.to_string_lossy();
+        Some(DerivativeNames {
+            display: format!("{}.display.{}", filename, extension),
+            webp: format!("{}.display.webp", filename),
+        })
+    }
+}
+
+impl MediaImage {
+    /// describes versions written by a previous sync
+    fn existing(
+        filename: &str,
+        names: &DerivativeNames,
+        dir: &Path,
+    ) -> Result<MediaImage, ImagingError> {
+        // display version may be missing, see `process`
+        let display = if dir.join(&names.display).exists() {
+            names.display.clone()
+        } else {
+            filename.to_owned()
+        };
+        let webp_path = dir.join(&names.webp);
+        let (width, height) =
+            image::image_dimensions(&webp_path).map_err(ImagingError::decode(&webp_path))?;
+        Ok(MediaImage {
+            display,
+            webp: names.webp.clone(),
+            width,
+            height,
+        })
+    }
+}
+
+/// derivatives are fresh if written after the source was last modified
+pub fn is_fresh(derivative: &Path, source: &Path) -> bool {
+    match (
+        derivative.metadata().and_then(|m| m.modified()),
+        source.metadata().and_then(|m| m.modified()),
+    ) {
+        (Ok(derivative_modified), Ok(source_modified)) => derivative_modified >= source_modified,
+        _ => false,
+    }
+}
+
+fn link(source: &Path, target: &Path) -> Result<(), ImagingError> {
+    // hard links share the metadata with the source, so they are always considered fresh, even
+    // after the source is modified in place
+    let same_len = match (target.metadata(), source.metadata()) {
+        (Ok(target_meta), Ok(source_meta)) => target_meta.len() == source_meta.len(),
+        _ => false,
+    };
+    if same_len && is_fresh(target, source) {
+        return Ok(());
+    }
+    if target.exists() {
+        fs::remove_file(target).map_err(ImagingError::io(target))?;
+    }
+    if fs::hard_link(source, target).is_err() {
+        fs::copy(source, target).map_err(ImagingError::io(target))?;
+    }
+    Ok(())
+}
+
+fn process(
+    file: &Path,
+    filename: &str,
+    names: &DerivativeNames,
+    target_dir: &Path,
+) -> Result<MediaImage, ImagingError> {
+    let img = image::open(file).map_err(ImagingError::decode(file))?;
+    let display_path = target_dir.join(&names.display);
+    let (display_img, display) = if img.width() > DISPLAY_MAX_WIDTH {
+        let resized = img.resize(DISPLAY_MAX_WIDTH, u32::MAX, FilterType::Lanczos3);
+        resized
+            .save(&display_path)
+            .map_err(ImagingError::encode(&display_path))?;
+        // downscaled screenshots are often heavier than the originals due to PNG compression
+        // working worse on smoothed pixels, in which case the browser may do the scaling
+        let display_len = fs::metadata(&display_path)
+            .map_err(ImagingError::io(&display_path))?
+            .len();
+        let original_len = fs::metadata(file).map_err(ImagingError::io(file))?.len();
+        if display_len < original_len {
+            (resized, names.display.clone())
+        } else {
+            fs::remove_file(&display_path).map_err(ImagingError::io(&display_path))?;
+            (resized, filename.to_owned())
+        }
+    } else {
+        if display_path.exists() {
+            fs::remove_file(&display_path).map_err(ImagingError::io(&display_path))?;
+        }
+        (img.clone(), filename.to_owned())
+    };
+
+    // WebP version tells whether the derivatives are up to date, so it goes last
+    VariantFormat::Webp.save(&display_img, WEBP_QUALITY, &target_dir.join(&names.webp))?;
+
+    let (width, height) = display_img.dimensions();
+    Ok(MediaImage {
+        display,
+        webp: names.webp.clone(),
+        width,
+        height,
+    })
+}
@@ -0,0 +1,393 @@
+use comrak::{nodes::NodeValue, Arena};
+use jiff::Timestamp;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+
+use crate::html::escape;
+use crate::project::{markdown_options, Project, ProjectCatalog, TagFilter};
+
+const TITLE_WEIGHT: f32 = 5.0;
+const TAG_WEIGHT: f32 = 3.0;
+const LINK_WEIGHT: f32 = 2.0;
+const BODY_WEIGHT: f32 = 1.0;
+
+/// shorter query terms only match whole words, longer ones match word prefixes too
+const MIN_PREFIX_LEN: usize = 3;
+const SNIPPET_CONTEXT_BEFORE: usize = 60;
+const SNIPPET_LENGTH: usize = 200;
+
+/// in-memory inverted index over the project catalog; documents are identified by their
+/// position in `ProjectCatalog.projects`
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    /// term -> (document, weighted term frequency); ordered, so that the terms sharing a prefix
+    /// are a contiguous range
+    postings: BTreeMap<String, Vec<(usize, f32)>>,
+    /// plain text of each document's body, used for snippets
+    texts: Vec<String>,
+}
+
+pub struct SearchHit {
+    pub doc: usize,
+    pub score: f32,
+    /// body excerpt around the first match, HTML-escaped with matches wrapped in `<mark>`
+    pub snippet_html: String,
+}
+
+impl SearchIndex {
+    pub fn build(projects: &[Project]) -> SearchIndex {
+        let mut postings: HashMap<String, HashMap<usize, f32>> = HashMap::new();
+        let mut texts = Vec::with_capacity(projects.len());
+        for (doc, project) in projects.iter().enumerate() {
+            let body_text = plain_text(project.body_md());
+            let mut add = |text: &str, weight: f32| {
+                for (_, token) in tokenize(text) {
+                    *postings.entry(token).or_default().entry(doc).or_default() += weight;
+                }
+            };
+            add(&project.metadata.title, TITLE_WEIGHT);
+            for tag in project.metadata.tags.iter() {
+                add(&tag.category, TAG_WEIGHT);
+                add(&tag.name, TAG_WEIGHT);
+            }
+            for link in project.metadata.links.iter() {
+                add(&link.name, LINK_WEIGHT);
+            }
+            add(&body_text, BODY_WEIGHT);
+            texts.push(body_text);
+        }
+        SearchIndex {
+            postings: postings
+                .into_iter()
+                .map(|(term, docs)| (term, docs.into_iter().collect()))
+                .collect(),
+            texts,
+        }
+    }
+
+    /// returns documents matching all of the query terms, best matches first
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        let terms = query_terms(query);
+        if terms.is_empty() {
+            return Vec::new();
+        }
+        let doc_count = self.texts.len() as f32;
+        let mut scores: HashMap<usize, (usize, f32)> = HashMap::new();
+        for term in terms.iter() {
+            let mut term_scores: HashMap<usize, f32> = HashMap::new();
+            for docs in self.matching_postings(term) {
+                let idf = (1.0 + doc_count / docs.len() as f32).ln();
+                for (doc, tf) in docs {
+                    *term_scores.entry(*doc).or_default() += tf * idf;
+                }
+            }
+            for (doc, score) in term_scores {
+                let entry = scores.entry(doc).or_default();
+                entry.0 += 1;
+                entry.1 += score;
+            }
+        }
+        let mut hits: Vec<SearchHit> = scores
+            .into_iter()
+            .filter(|(_, (matched_terms, _))| *matched_terms == terms.len())
+            .map(|(doc, (_, score))| SearchHit {
+                doc,
+                score,
+                snippet_html: snippet(&self.texts[doc], &terms),
+            })
+            .collect();
+        // documents are sorted newest first, so that's our tie-breaker
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.doc.cmp(&b.doc)));
+        hits
+    }
+
+    /// postings of the index terms matching the query term, see `term_matches`
+    fn matching_postings<'a>(
+        &'a self,
+        term: &'a str,
+    ) -> Box<dyn Iterator<Item = &'a Vec<(usize, f32)>> + 'a> {
+        if term.chars().count() >= MIN_PREFIX_LEN {
+            Box::new(
+                self.postings
+                    .range::<str, _>((Bound::Included(term), Bound::Unbounded))
+                    .take_while(move |(index_term, _)| index_term.starts_with(term))
+                    .map(|(_, docs)| docs),
+            )
+        } else {
+            Box::new(self.postings.get(term).into_iter())
+        }
+    }
+}
+
+pub struct SearchResult<'a> {
+    pub project: &'a Project,
+    /// project title, HTML-escaped with matches wrapped in `<mark>`
+    pub title_html: String,
+    pub snippet_html: String,
+}
+
+/// projects matching the text query and the tag filter; with an empty query, all projects
+/// matching the filter are returned, and with neither, nothing is
+pub fn search_projects<'a>(
+    catalog: &'a ProjectCatalog,
+    query: &str,
+    filter: &TagFilter,
+) -> Vec<SearchResult<'a>> {
+    let terms = query_terms(query);
+    let now = Timestamp::now();
+    if !terms.is_empty() {
+        catalog
+            .search_index
+            .search(query)
+            .into_iter()
+            .map(|hit| (&catalog.projects[hit.doc], hit.snippet_html))
+            .filter(|(p, _)| p.is_published(now) && filter.matches(p))
+            .map(|(project, snippet_html)| SearchResult {
+                project,
+                title_html: highlight(&project.metadata.title, &terms),
+                snippet_html,
+            })
+            .collect()
+    } else if !filter.is_empty() {
+        catalog
+            .published()
+            .filter(|p| filter.matches(p))
+            .map(|project| SearchResult {
+                project,
+                title_html: highlight(&project.metadata.title, &terms),
+                snippet_html: String::new(),
+            })
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+pub fn query_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = tokenize(query).map(|(_, t)| t).collect();
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+fn term_matches(query_term: &str, index_term: &str) -> bool {
+    if query_term.chars().count() >= MIN_PREFIX_LEN {
+        index_term.starts_with(query_term)
+    } else {
+        index_term == query_term
+    }
+}
+
+/// splits text into lowercase alphanumeric words, along with their byte ranges in the text
+fn tokenize(text: &str) -> impl Iterator<Item = ((usize, usize), String)> + '_ {
+    let mut chars = text.char_indices().peekable();
+    std::iter::from_fn(move || {
+        while chars.peek().is_some_and(|(_, c)| !c.is_alphanumeric()) {
+            chars.next();
+        }
+        let (start, _) = *chars.peek()?;
+        let mut end = start;
+        while let Some((i, c)) = chars.peek().copied() {
+            if !c.is_alphanumeric() {
+                break;
+            }
+            end = i + c.len_utf8();
+            chars.next();
+        }
+        Some(((start, end), text[start..end].to_lowercase()))
+    })
+}
+
+/// renders Markdown text content, skipping markup, link URLs and raw HTML
+pub fn plain_text(md: &str) -> String {
+    let arena = Arena::new();
+    let root = comrak::parse_document(&arena, md, &markdown_options());
+    let mut text = String::new();
+    for node in root.descendants() {
+        match &node.data().value {
+            NodeValue::Text(t) => text.push_str(t),
+            NodeValue::Code(code) => text.push_str(&code.literal),
+            NodeValue::CodeBlock(block) => {
+                text.push_str(&block.literal);
+                text.push(' ');
+            }
+            NodeValue::SoftBreak | NodeValue::LineBreak => text.push(' '),
+            NodeValue::Paragraph | NodeValue::Heading(_) | NodeValue::Item(_)
+                if !text.is_empty() && !text.ends_with(' ') =>
+            {
+                text.push(' ')
+            }
+            _ => {}
+        }
+    }
+    text
+}
+
+/// HTML-escapes the text, wrapping words matching any of the terms in `<mark>`
+pub fn highlight(text: &str, terms: &[String]) -> String {
+    let mut html = String::with_capacity(text.len());
+    let mut last = 0;
+    for ((start, end), token) in tokenize(text) {
+        if terms.iter().any(|t| term_matches(t, &token)) {
+            html.push_str(&escape(&text[last..start]));
+            html.push_str("<mark>");
+            html.push_str(&escape(&text[start..end]));
+            html.push_str("</mark>");
+            last = end;
+        }
+    }
+    html.push_str(&escape(&text[last..]));
+    html
+}
+
+fn snippet(text: &str, terms: &[String]) -> String {
+    let first_match = tokenize(text)
+        .find(|(_, token)| terms.iter().any(|t| term_matches(t, token)))
+        .map(|((start, _), _)| start)
+        .unwrap_or(0);
+    let mut start = floor_char_boundary(text, first_match.saturating_sub(SNIPPET_CONTEXT_BEFORE));
+    // not starting the snippet mid-word
+    if start > 0 {
+        start = text[start..first_match]
+            .find(' ')
+            .map_or(first_match, |i| start + i + 1);
+    }
+    let end = floor_char_boundary(text, start + SNIPPET_LENGTH);
+    let mut snippet = highlight(&text[start..end], terms);
+    if start > 0 {
+        snippet.insert(0, '…');
+    }
+    if end < text.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    if index >= text.len() {
+        return text.len();
+    }
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(query: &str) -> Vec<String> {
+        query_terms(query)
+    }
+
+    #[test]
+    fn tokenize_words_and_ranges() {
+        let text = "Hello, Wörld! Привет_мир 42";
+        let tokens: Vec<_> = tokenize(text).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                ((0, 5), "hello".to_owned()),
+                ((7, 13), "wörld".to_owned()),
+                ((15, 27), "привет".to_owned()),
+                ((28, 34), "мир".to_owned()),
+                ((35, 37), "42".to_owned()),
+            ]
+        );
+        for ((start, end), token) in tokens {
+            assert_eq!(text[start..end].to_lowercase(), token);
+        }
+        assert_eq!(tokenize("  ...  ").count(), 0);
+    }
+
+    #[test]
+    fn query_terms_are_sorted_and_deduplicated() {
+        assert_eq!(terms("Rust web rust"), vec!["rust", "web"]);
+    }
+
+    #[test]
+    fn search_matches_prefixes_of_long_terms_only() {
+        let mut postings: BTreeMap<String, Vec<(usize, f32)>> = BTreeMap::new();
+        postings.insert("rust".to_owned(), vec![(0, 1.0)]);
+        postings.insert("rustic".to_owned(), vec![(1, 1.0)]);
+        postings.insert("rusty".to_owned(), vec![(1, 1.0)]);
+        postings.insert("ru".to_owned(), vec![(2, 1.0)]);
+        postings.insert("rv".to_owned(), vec![(2, 1.0)]);
+        let index = SearchIndex {
+            postings,
+            texts: vec![String::new(); 3],
+        };
+        let docs = |query: &str| {
+            let mut docs: Vec<usize> = index.search(query).iter().map(|h| h.doc).collect();
+            docs.sort();
+            docs
+        };
+        assert_eq!(docs("rust"), vec![0, 1]);
+        assert_eq!(docs("rusti"), vec![1]);
+        assert_eq!(docs("ru"), vec![2]);
+        assert_eq!(docs("r"), Vec::<usize>::new());
+        assert_eq!(docs("rust ru"), Vec::<usize>::new());
+        assert_eq!(docs(""), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn highlight_escapes_and_marks() {
+        assert_eq!(
+            highlight("Rustic <tools> & rust", &terms("rust")),
+            "<mark>Rustic</mark> &lt;tools&gt; &amp; <mark>rust</mark>"
+        );
+        // short terms only match whole words
+        assert_eq!(
+            highlight("go gopher", &terms("go")),
+            "<mark>go</mark> gopher"
+        );
+        assert_eq!(
+            highlight("Ёжик в тумане", &terms("ёжи")),
+            "<mark>Ёжик</mark> в тумане"
+        );
+    }
+
+    #[test]
+    fn snippet_short_text() {
+        assert_eq!(
+            snippet("a tiny rust project", &terms("rust")),
+            "a tiny <mark>rust</mark> project"
+        );
+        // no match still shows the beginning
+        assert_eq!(snippet("a tiny project", &terms("rust")), "a tiny project");
+    }
+
+    #[test]
+    fn snippet_starts_at_a_word_near_the_match() {
+        let text = format!("{} needle {}", "word ".repeat(40), "tail ".repeat(60));
+        let html = snippet(&text, &terms("needle"));
+        assert!(html.starts_with("…word "));
+        assert!(html.ends_with('…'));
+        assert!(html.contains("<mark>needle</mark>"));
+    }
+
+    #[test]
+    fn snippet_multibyte_boundaries() {
+        // two-byte letters after an odd offset, so that both snippet boundaries land mid-letter
+        let text = format!("a{} иголка {}", "ж".repeat(45), "щ".repeat(150));
+        let html = snippet(&text, &terms("иголка"));
+        assert!(html.starts_with("…<mark>иголка</mark> щ"));
+        assert!(html.ends_with('…'));
+        assert!(html.contains("<mark>иголка</mark>"));
+        // four-byte characters
+        let text = format!("{} 🦀rust {}", "🦀".repeat(20), "🦀".repeat(60));
+        let html = snippet(&text, &terms("rust"));
+        assert!(html.contains("<mark>rust</mark>"));
+        assert!(html.ends_with('…'));
+    }
+
+    #[test]
+    fn floor_char_boundary_steps_back() {
+        let text = "aжb";
+        assert_eq!(floor_char_boundary(text, 0), 0);
+        assert_eq!(floor_char_boundary(text, 2), 1);
+        assert_eq!(floor_char_boundary(text, 3), 3);
+        assert_eq!(floor_char_boundary(text, 10), text.len());
+    }
+}
@@ -0,0 +1,143 @@
+use askama::Template;
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use jiff::{tz::TimeZone, Timestamp};
+
+use crate::{imaging, project::Project, AppState};
+
+/// pages worth indexing besides the projects and gallery images
+const PAGES: [&str; 6] = [
+    "/",
+    "/projects",
+    "/tags",
+    "/music",
+    "/music/tracks",
+    "/gallery",
+];
+
+pub struct SitemapEntry {
+    pub url: String,
+    pub lastmod: Option<Timestamp>,
+}
+
+impl SitemapEntry {
+    /// W3C date format, the only precision we care about
+    pub fn lastmod_date(&self) -> Option<String> {
+        self.lastmod
+            .map(|ts| ts.to_zoned(TimeZone::UTC).date().to_string())
+    }
+}
+
+#[derive(Template)]
+#[template(path = "sitemap.xml")]
+struct Sitemap {
+    entries: Vec<SitemapEntry>,
+}
+
+#[derive(Template)]
+#[template(
+    source = "User-agent: *
+{% for path in disallow %}Disallow: {{ path }}
+{% endfor %}
+Sitemap: {{ sitemap_url }}
+",
+    ext = "txt"
+)]
+struct Robots<'a> {
+    disallow: &'a [String],
+    sitemap_url: String,
+}
+
+/// finished projects were last modified when they ended (or started, for one-off ones), while
+/// ongoing ones are updated along the way
+fn project_lastmod(project: &Project) -> Option<Timestamp> {
+    let metadata = &project.metadata;
+    if let Some(end) = &metadata.end {
+        return Some(end.to_timestamp());
+    }
+    ["meta.yaml", "body.md"]
+        .iter()
+        .filter_map(|file| project.dir.join(file).metadata().ok()?.modified().ok())
+        .filter_map(|modified| Timestamp::try_from(modified).ok())
+        .max()
+        .or(Some(metadata.start.to_timestamp()))
+}
+
+pub async fn sitemap(State(state): State<AppState>) -> Response {
+    let mut entries: Vec<SitemapEntry> = PAGES
+        .iter()
+        .map(|page| SitemapEntry {
+            url: format!("{}{}", state.base_url, page),
+            lastmod: None,
+        })
+        .collect();
+
+    let catalog = state.project_catalog.load();
+    entries.extend(catalog.published().map(|p| SitemapEntry {
+        url: format!("{}/projects/{}", state.base_url, p.metadata.slug),
+        lastmod: project_lastmod(p),
+    }));
+
+    let gallery = state.gallery.load();
+    entries.extend(gallery.images.iter().map(|img| {
+        SitemapEntry {
+            url: format!(
+                "{}/gallery/{}",
+                state.base_url,
+                imaging::escape_url_path(&img.filename)
+            ),
+            lastmod: img
+                .timestamp
+                .to_zoned(TimeZone::UTC)
+                .map(|z| z.timestamp())
+                .ok(),
+        }
+    }));
+
+    entries.push(SitemapEntry {
+        url: format!("{}/gallery/albums", state.base_url),
+        lastmod: None,
+    });
+    entries.extend(gallery.albums.iter().map(|album| {
+        SitemapEntry {
+            url: format!("{}/gallery/albums/{}", state.base_url, album.slug),
+            lastmod: gallery
+                .album_images(album)
+                .iter()
+                .filter_map(|img| img.timestamp.to_zoned(TimeZone::UTC).ok())
+                .map(|z| z.timestamp())
+                .max(),
+        }
+    }));
+
+    let audio_catalog = state.audio_catalog.load();
+    entries.extend(audio_catalog.tracks.iter().map(|track| SitemapEntry {
+        url: format!("{}/music/tracks/{}", state.base_url, track.slug),
+        lastmod: None,
+    }));
+
+    match (Sitemap { entries }).render() {
+        Ok(body) => ([(header::CONTENT_TYPE, "application/xml")], body).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to render sitemap: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn robots(State(state): State<AppState>) -> Response {
+    let robots = Robots {
+        disallow: &state.robots_disallow,
+        sitemap_url: format!("{}/sitemap.xml", state.base_url),
+    };
+    match robots.render() {
+        Ok(body) => ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to render robots.txt: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
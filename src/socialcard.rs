@@ -0,0 +1,148 @@
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+use image::{ImageFormat, Rgb, RgbImage};
+use std::io::Cursor;
+
+/// recommended OpenGraph image size
+pub const WIDTH: u32 = 1200;
+pub const HEIGHT: u32 = 630;
+
+const MARGIN: u32 = 80;
+const TITLE_SIZE: f32 = 72.0;
+const FOOTER_SIZE: f32 = 36.0;
+const MAX_TITLE_LINES: usize = 4;
+
+// site palette, see static/style.css
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+const TEXT: Rgb<u8> = Rgb([0, 0, 0]);
+const SECONDARY_BLUE: Rgb<u8> = Rgb([128, 128, 255]);
+const LIGHT_GRAY: Rgb<u8> = Rgb([184, 184, 184]);
+
+/// monospace, like the site itself
+const FONT: &[u8] = include_bytes!("../static/fonts/DejaVuSansMono-Bold.ttf");
+
+/// image shown in link previews, with a site-relative URL
+#[derive(Debug, Clone)]
+pub struct SocialImage {
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// social preview image with the title on a plain background, PNG-encoded
+pub fn render(title: &str) -> Result<Vec<u8>, image::ImageError> {
+    let font = FontRef::try_from_slice(FONT).expect("bundled font is valid");
+    let mut img = RgbImage::from_pixel(WIDTH, HEIGHT, BACKGROUND);
+
+    // accent stripe along the left edge
+    for x in 0..MARGIN / 4 {
+        for y in 0..HEIGHT {
+            img.put_pixel(x, y, SECONDARY_BLUE);
+        }
+    }
+
+    let title_font = font.as_scaled(PxScale::from(TITLE_SIZE));
+    let lines = wrap(title, &title_font, (WIDTH - 2 * MARGIN) as f32);
+    let mut baseline = MARGIN as f32 + title_font.ascent();
+    for line in lines.iter() {
+        draw_text(
+            &mut img,
+            &font,
+            TITLE_SIZE,
+            line,
+            MARGIN as f32,
+            baseline,
+            TEXT,
+        );
+        baseline += title_font.height() + title_font.line_gap();
+    }
+
+    let footer_font = font.as_scaled(PxScale::from(FOOTER_SIZE));
+    let footer_baseline = (HEIGHT - MARGIN) as f32 + footer_font.descent();
+    let separator_y = footer_baseline as u32 - footer_font.ascent() as u32 - MARGIN / 4;
+    for x in MARGIN..WIDTH - MARGIN {
+        img.put_pixel(x, separator_y, LIGHT_GRAY);
+    }
+    draw_text(
+        &mut img,
+        &font,
+        FOOTER_SIZE,
+        "nj-vs-vh",
+        MARGIN as f32,
+        footer_baseline,
+        SECONDARY_BLUE,
+    );
+
+    let mut png = Cursor::new(Vec::new());
+    img.write_to(&mut png, ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+fn text_width<F: Font>(text: &str, font: &impl ScaleFont<F>) -> f32 {
+    text.chars().map(|c| font.h_advance(font.glyph_id(c))).sum()
+}
+
+/// breaks the text into lines fitting into `max_width`, truncating it with an ellipsis if there
+/// are too many of them
+fn wrap<F: Font>(text: &str, font: &impl ScaleFont<F>, max_width: f32) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() {
+            word.to_owned()
+        } else {
+            format!("{} {}", line, word)
+        };
+        if line.is_empty() || text_width(&candidate, font) <= max_width {
+            line = candidate;
+        } else {
+            lines.push(std::mem::replace(&mut line, word.to_owned()));
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    if lines.len() > MAX_TITLE_LINES {
+        lines.truncate(MAX_TITLE_LINES);
+        if let Some(last) = lines.last_mut() {
+            last.push('…');
+        }
+    }
+    lines
+}
+
+fn draw_text(
+    img: &mut RgbImage,
+    font: &FontRef,
+    size: f32,
+    text: &str,
+    x: f32,
+    baseline: f32,
+    color: Rgb<u8>,
+) {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let mut caret = x;
+    for c in text.chars() {
+        let glyph = scaled.scaled_glyph(c);
+        let advance = scaled.h_advance(glyph.id);
+        let glyph = ab_glyph::Glyph {
+            position: ab_glyph::point(caret, baseline),
+            ..glyph
+        };
+        caret += advance;
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i32 + gx as i32;
+            let py = bounds.min.y as i32 + gy as i32;
+            if px < 0 || py < 0 || px >= WIDTH as i32 || py >= HEIGHT as i32 {
+                return;
+            }
+            let pixel = img.get_pixel_mut(px as u32, py as u32);
+            for (channel, target) in pixel.0.iter_mut().zip(color.0) {
+                *channel = (*channel as f32 * (1.0 - coverage) + target as f32 * coverage) as u8;
+            }
+        });
+    }
+}
@@ -1,11 +1,12 @@
 use crate::project::Project;
+use crate::socialcard::SocialImage;
 use askama::Template;
 
 #[derive(Template)]
 #[template(
     source = "
     <span>
-        <a href=\"projects/{{ p.metadata.slug }}\">{{ p.metadata.title }}</a>
+        <a href=\"/projects/{{ p.metadata.slug }}\">{{ p.metadata.title }}</a>
         {% if p.metadata.tags.len() > 0 %}
             <span style=\"font-size: smaller;\">
                 {% for tag in p.metadata.tags %}
@@ -24,3 +25,11 @@ use askama::Template;
 pub struct ProjectHyperlink<'a> {
     pub p: &'a Project,
 }
+
+/// OpenGraph and Twitter card metadata, rendered by `social_meta.html`; URLs are absolute
+pub struct SocialMeta {
+    pub title: String,
+    pub description: String,
+    pub url: String,
+    pub image: SocialImage,
+}
@@ -0,0 +1,382 @@
+use axum::{
+    extract::{Path as UrlPath, Query, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Component, Path, PathBuf},
+    process::Stdio,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+use crate::audio::{AudioCatalog, AudioTrack};
+use crate::errorpage::ErrorPage;
+use crate::imaging;
+use crate::projectmedia::is_fresh;
+use crate::AppState;
+
+/// originals in these formats are worth offering in a lighter one
+const LOSSLESS_EXTENSIONS: [&str; 2] = ["flac", "wav"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum TranscodeError {
+    #[error("Failed to access {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("Failed to run {ffmpeg:?}: {source}")]
+    Spawn { ffmpeg: PathBuf, source: io::Error },
+    #[error("Failed to transcode {path:?}: {stderr}")]
+    Ffmpeg { path: PathBuf, stderr: String },
+}
+
+impl TranscodeError {
+    fn io(path: &Path) -> impl FnOnce(io::Error) -> TranscodeError + '_ {
+        move |source| TranscodeError::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Opus,
+    Mp3,
+}
+
+impl AudioFormat {
+    /// preferred first
+    pub const ALL: [AudioFormat; 2] = [AudioFormat::Opus, AudioFormat::Mp3];
+
+    pub fn parse(name: &str) -> Option<AudioFormat> {
+        match name {
+            "opus" => Some(AudioFormat::Opus),
+            "mp3" => Some(AudioFormat::Mp3),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioFormat::Opus => "opus",
+            AudioFormat::Mp3 => "mp3",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            AudioFormat::Opus => "audio/ogg; codecs=opus",
+            AudioFormat::Mp3 => "audio/mpeg",
+        }
+    }
+
+    fn ffmpeg_args(&self) -> [&'static str; 4] {
+        match self {
+            AudioFormat::Opus => ["-c:a", "libopus", "-f", "ogg"],
+            AudioFormat::Mp3 => ["-c:a", "libmp3lame", "-f", "mp3"],
+        }
+    }
+}
+
+/// a way to play the track, in the order of preference for the `<audio>` element
+pub struct AudioSource {
+    pub url: String,
+    pub mime_type: &'static str,
+    /// e.g. "opus 128k"
+    pub label: String,
+}
+
+/// serves the audio dir, transcoding the files on request; variants are cached as
+/// `<file>.<bitrate>k.<format>` in the cache dir and kept until the originals change
+pub struct AudioFiles {
+    pub audio_dir: PathBuf,
+    pub cache_dir: PathBuf,
+    /// `None` if ffmpeg is not available, in which case only the originals are served
+    pub ffmpeg: Option<PathBuf>,
+    /// in kbps, the only ones accepted to keep the cache bounded
+    pub bitrates: Vec<u32>,
+    pub default_bitrate: u32,
+    /// transcoding the same variant twice at once is a waste
+    locks: Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>,
+    /// limits ffmpeg processes running at once to the number of CPUs
+    transcodes: tokio::sync::Semaphore,
+    /// distinguishes temporary files of the transcoding attempts
+    attempts: AtomicUsize,
+}
+
+impl AudioFiles {
+    pub fn new(
+        audio_dir: PathBuf,
+        cache_dir: PathBuf,
+        ffmpeg: PathBuf,
+        mut bitrates: Vec<u32>,
+        default_bitrate: u32,
+    ) -> AudioFiles {
+        let available = std::process::Command::new(&ffmpeg)
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if available {
+            tracing::info!("Transcoding audio with {:?}", ffmpeg);
+        } else {
+            tracing::warn!(
+                "{:?} is not available, audio is served without transcoding",
+                ffmpeg
+            );
+        }
+        if !bitrates.contains(&default_bitrate) {
+            bitrates.push(default_bitrate);
+        }
+        bitrates.sort();
+        AudioFiles {
+            audio_dir,
+            cache_dir,
+            ffmpeg: available.then_some(ffmpeg),
+            bitrates,
+            default_bitrate,
+            locks: Mutex::new(HashMap::new()),
+            transcodes: tokio::sync::Semaphore::new(
+                std::thread::available_parallelism().map_or(1, |n| n.get()),
+            ),
+            attempts: AtomicUsize::new(0),
+        }
+    }
+
+    /// sources for the track's player: lossless originals are preceded by the transcoded
+    /// variants at the default bitrate
+    pub fn sources(&self, track: &AudioTrack) -> Vec<AudioSource> {
+        let url = format!("/audio/{}", imaging::escape_url_path(&track.file));
+        let mut sources = Vec::new();
+        if self.ffmpeg.is_some() && is_lossless(&track.file) {
+            for format in AudioFormat::ALL {
+                sources.push(AudioSource {
+                    url: format!(
+                        "{}?format={}&bitrate={}",
+                        url,
+                        format.name(),
+                        self.default_bitrate
+                    ),
+                    mime_type: format.mime_type(),
+                    label: format!("{} {}k", format.name(), self.default_bitrate),
+                });
+            }
+        }
+        sources.push(AudioSource {
+            url,
+            mime_type: track.mime_type(),
+            label: "original".to_owned(),
+        });
+        sources
+    }
+
+    fn variant_path(&self, file: &str, format: AudioFormat, bitrate: u32) -> PathBuf {
+        self.cache_dir
+            .join(format!("{}.{}k.{}", file, bitrate, format.name()))
+    }
+
+    /// path to the cached variant of the original, transcoding it if needed
+    async fn variant(
+        &self,
+        ffmpeg: &Path,
+        file: &str,
+        format: AudioFormat,
+        bitrate: u32,
+    ) -> Result<PathBuf, TranscodeError> {
+        let source = self.audio_dir.join(file);
+        let target = self.variant_path(file, format, bitrate);
+        if is_fresh(&target, &source) {
+            return Ok(target);
+        }
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(target.clone())
+            .or_default()
+            .clone();
+        let _guard = lock.lock().await;
+        // might have been transcoded while we were waiting
+        if is_fresh(&target, &source) {
+            return Ok(target);
+        }
+        let _permit = self
+            .transcodes
+            .acquire()
+            .await
+            .expect("transcoding semaphore is never closed");
+
+        tracing::info!(
+            "Transcoding {:?} into {} {}k",
+            source,
+            format.name(),
+            bitrate
+        );
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(TranscodeError::io(parent))?;
+        }
+        // written under a temporary name unique to the attempt so that an interrupted transcoding
+        // is never served; ffmpeg is killed if the request is dropped, and the leftovers are
+        // removed as stale on the next start
+        let tmp = target.with_extension(format!(
+            "{}.{}-{}.tmp",
+            format.name(),
+            std::process::id(),
+            self.attempts.fetch_add(1, Ordering::Relaxed)
+        ));
+        let output = tokio::process::Command::new(ffmpeg)
+            .args(["-nostdin", "-v", "error", "-y", "-i"])
+            .arg(&source)
+            .args(["-vn", "-map_metadata", "0", "-b:a"])
+            .arg(format!("{}k", bitrate))
+            .args(format.ffmpeg_args())
+            .arg(&tmp)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|source| TranscodeError::Spawn {
+                ffmpeg: ffmpeg.to_owned(),
+                source,
+            })?;
+        if !output.status.success() {
+            let _ = fs::remove_file(&tmp);
+            return Err(TranscodeError::Ffmpeg {
+                path: source,
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            });
+        }
+        fs::rename(&tmp, &target).map_err(TranscodeError::io(&target))?;
+        Ok(target)
+    }
+
+    /// drops cached variants of the originals that are not in the catalog anymore, as well
+    /// as the ones at the bitrates no longer accepted
+    pub fn remove_stale(&self, catalog: &AudioCatalog) {
+        let expected: HashSet<PathBuf> = catalog
+            .tracks
+            .iter()
+            .flat_map(|track| {
+                AudioFormat::ALL.into_iter().flat_map(move |format| {
+                    self.bitrates
+                        .iter()
+                        .map(move |&bitrate| self.variant_path(&track.file, format, bitrate))
+                })
+            })
+            .collect();
+        let mut cached = Vec::new();
+        list_files(&self.cache_dir, &mut cached);
+        for path in cached.into_iter().filter(|path| !expected.contains(path)) {
+            tracing::debug!("Removing stale audio variant {:?}", path);
+            if let Err(e) = fs::remove_file(&path) {
+                tracing::warn!("Failed to remove stale audio variant {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+fn list_files(dir: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = dir.read_dir() else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            list_files(&path, found);
+        } else {
+            found.push(path);
+        }
+    }
+}
+
+fn is_lossless(file: &str) -> bool {
+    Path::new(file).extension().is_some_and(|ext| {
+        LOSSLESS_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+    })
+}
+
+/// original audio file or, with the `format` (and optionally `bitrate`) query parameters, its
+/// transcoded variant; both support range requests for seeking
+pub async fn audio_file(
+    State(state): State<AppState>,
+    UrlPath(file): UrlPath<String>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request,
+) -> Result<Response, ErrorPage> {
+    let files = &state.audio_files;
+    let is_safe = Path::new(&file)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    if !is_safe {
+        return Err(ErrorPage::not_found("there is no such audio file"));
+    }
+
+    let Some(format) = params.get("format") else {
+        let response = ServeFile::new(files.audio_dir.join(&file))
+            .oneshot(request)
+            .await
+            .map_err(|e| match e {})?;
+        return Ok(response.into_response());
+    };
+    let format = AudioFormat::parse(format).ok_or_else(|| {
+        ErrorPage::new(StatusCode::BAD_REQUEST).with_message(format!(
+            "unknown audio format {:?}, available ones are {}",
+            format,
+            AudioFormat::ALL.map(|f| f.name()).join(", ")
+        ))
+    })?;
+    let bitrate = match params.get("bitrate") {
+        None => files.default_bitrate,
+        Some(bitrate) => bitrate
+            .trim_end_matches('k')
+            .parse()
+            .ok()
+            .filter(|b| files.bitrates.contains(b))
+            .ok_or_else(|| {
+                ErrorPage::new(StatusCode::BAD_REQUEST).with_message(format!(
+                    "unsupported bitrate {:?}, available ones are {}",
+                    bitrate,
+                    files
+                        .bitrates
+                        .iter()
+                        .map(|b| format!("{}k", b))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            })?,
+    };
+    // only the files known to the catalog are transcoded, nothing else is worth the effort
+    if state.audio_catalog.load().find_by_file(&file).is_none() {
+        return Err(ErrorPage::not_found("there is no such audio file"));
+    }
+    let Some(ffmpeg) = &files.ffmpeg else {
+        return Err(ErrorPage::new(StatusCode::SERVICE_UNAVAILABLE)
+            .with_message("audio transcoding is not available, try the original file"));
+    };
+
+    let variant = files
+        .variant(ffmpeg, &file, format, bitrate)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            ErrorPage::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .with_message("failed to transcode the audio file")
+        })?;
+    let mut response = ServeFile::new(variant)
+        .oneshot(request)
+        .await
+        .map_err(|e| match e {})?
+        .into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.mime_type()),
+    );
+    Ok(response)
+}
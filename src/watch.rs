@@ -0,0 +1,126 @@
+use arc_swap::ArcSwap;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::HashSet,
+    path::{Component, Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    gallery::{self, Gallery, ImageLocks, ProcessingParams},
+    galleryalbums,
+    gallerycache::CacheManifest,
+    project::ProjectCatalog,
+    ContentDirs,
+};
+
+/// editors and image processing tools tend to produce bursts of events, so we wait for things
+/// to settle down before reloading
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// watches project and gallery dirs, reloading affected projects and images into shared state;
+/// the watching stops when the returned watcher is dropped
+pub fn watch(
+    dirs: &ContentDirs,
+    project_catalog: Arc<ArcSwap<ProjectCatalog>>,
+    gallery: Arc<ArcSwap<Gallery>>,
+    processing_params: Arc<ProcessingParams>,
+    gallery_cache: Arc<Mutex<CacheManifest>>,
+    gallery_locks: Arc<ImageLocks>,
+) -> notify::Result<RecommendedWatcher> {
+    let (tx, rx) = mpsc::channel::<notify::Result<notify::Event>>();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    // events are reported with absolute paths, so we need canonical dirs to match them against
+    let projects_dir_abs = dirs.projects_dir.canonicalize()?;
+    let gallery_dir_abs = dirs.gallery_dir.canonicalize()?;
+    watcher.watch(&projects_dir_abs, RecursiveMode::Recursive)?;
+    watcher.watch(&gallery_dir_abs, RecursiveMode::NonRecursive)?;
+    tracing::info!(
+        "Watching {:?} and {:?} for changes",
+        projects_dir_abs,
+        gallery_dir_abs
+    );
+
+    let dirs = dirs.clone();
+    std::thread::spawn(move || {
+        while let Ok(first) = rx.recv() {
+            let mut changed_projects: HashSet<PathBuf> = HashSet::new();
+            let mut changed_images: HashSet<PathBuf> = HashSet::new();
+            let mut next = Some(first);
+            while let Some(result) = next {
+                match result {
+                    // reading files while reloading produces access events, which must be
+                    // ignored to avoid reloading forever
+                    Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                        for path in event.paths {
+                            if let Some(name) = first_component(&path, &projects_dir_abs) {
+                                changed_projects.insert(dirs.projects_dir.join(name));
+                            } else if let Some(name) = first_component(&path, &gallery_dir_abs) {
+                                changed_images.insert(dirs.gallery_dir.join(name));
+                            }
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("Filesystem watcher error: {}", e),
+                }
+                next = rx.recv_timeout(DEBOUNCE_TIMEOUT).ok();
+            }
+
+            for project_dir in changed_projects {
+                tracing::info!("Reloading project from {:?}", project_dir);
+                match project_catalog
+                    .load()
+                    .reload_project(&project_dir, &dirs.project_media_dir)
+                {
+                    Ok(catalog) => {
+                        tracing::info!("Reloaded project catalog: {}", &catalog);
+                        project_catalog.store(Arc::new(catalog));
+                    }
+                    Err(e) => tracing::warn!(
+                        "Failed to reload project catalog after {:?} changed: {}",
+                        project_dir,
+                        e
+                    ),
+                }
+            }
+
+            for image_path in changed_images {
+                // cache manifest is written next to the originals, so we skip our own writes
+                if image_path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+                {
+                    continue;
+                }
+                if image_path
+                    .file_name()
+                    .is_some_and(|name| name == galleryalbums::ALBUMS_FILENAME)
+                {
+                    tracing::info!("Reloading gallery albums from {:?}", image_path);
+                    gallery::reload_albums(&dirs.gallery_dir, &gallery);
+                    continue;
+                }
+                tracing::info!("Reloading gallery image {:?}", image_path);
+                gallery::reload_image(
+                    &image_path,
+                    &dirs,
+                    &processing_params,
+                    &gallery_cache,
+                    &gallery_locks,
+                    &gallery,
+                );
+                tracing::info!("Reloaded gallery: {}", gallery.load());
+            }
+        }
+    });
+
+    Ok(watcher)
+}
+
+fn first_component<'a>(path: &'a Path, dir: &Path) -> Option<&'a std::ffi::OsStr> {
+    match path.strip_prefix(dir).ok()?.components().next()? {
+        Component::Normal(name) => Some(name),
+        _ => None,
+    }
+}